@@ -0,0 +1,120 @@
+; Free DMG boot ROM, mapped at $0000-$00ff when no --boot-rom is given.
+;
+; Shows the cartridge's logo for about a second, then hands over to the
+; cartridge with the registers and IO state the original boot ROM leaves
+; behind. Unlike the original it doesn't scroll the logo or play the chime,
+; and it doesn't lock up on a bad logo or header checksum, so homebrew
+; without a valid header still runs.
+;
+; Build with:
+;   rgbasm -o dmg_boot.o dmg_boot.asm
+;   rgblink -x -o dmg_boot.bin dmg_boot.o
+
+SECTION "Boot ROM", ROM0[$0000]
+
+Start:
+    ld sp, $fffe
+
+    xor a
+    ld hl, $9fff
+.clearVRAM
+    ld [hl-], a
+    bit 7, h
+    jr nz, .clearVRAM
+
+    ; sound on, set up like the original does
+    ld hl, $ff26
+    ld c, $11
+    ld a, $80
+    ld [hl-], a ; NR52
+    ldh [c], a ; NR11
+    inc c
+    ld a, $f3
+    ldh [c], a ; NR12
+    ld [hl-], a ; NR51
+    ld a, $77
+    ld [hl], a ; NR50
+
+    ld a, $fc
+    ldh [$47], a ; BGP
+
+    ; the logo at $0104 becomes tiles $01-$18 at twice its size
+    ld de, $0104
+    ld hl, $8010
+.decodeLogo
+    ld a, [de]
+    call DecodeNibble
+    call DecodeLowNibble
+    inc de
+    ld a, e
+    cp $34
+    jr nz, .decodeLogo
+
+    ; two rows of 12 tiles in the middle of the screen
+    ld a, $01
+    ld hl, $9904
+    call WriteTileRow
+    ld l, $24
+    call WriteTileRow
+
+    ld a, $91
+    ldh [$40], a ; LCDC
+
+    ld b, 60
+.waitVBlank
+    ldh a, [$44]
+    cp $90
+    jr nz, .waitVBlank
+.waitVBlankEnd
+    ldh a, [$44]
+    cp $90
+    jr z, .waitVBlankEnd
+    dec b
+    jr nz, .waitVBlank
+
+    ; AF = $01b0, BC = $0013, DE = $00d8, HL = $014d like on a DMG
+    ld hl, $01b0
+    push hl
+    pop af
+    ld bc, $0013
+    ld de, $00d8
+    ld hl, $014d
+    jp Done
+
+; Write tiles a, a + 1, ... to 12 map entries starting at hl
+WriteTileRow:
+    ld b, 12
+.loop
+    ld [hl+], a
+    inc a
+    dec b
+    jr nz, .loop
+    ret
+
+; Every bit of the top nibble of a becomes two pixels on two rows.
+; DecodeLowNibble carries on with the bits DecodeNibble left in c.
+DecodeNibble:
+    ld c, a
+DecodeLowNibble:
+    ld b, 4
+.loop
+    push bc
+    rl c
+    rla
+    pop bc
+    rl c
+    rla
+    dec b
+    jr nz, .loop
+    ld [hl+], a
+    inc hl
+    ld [hl+], a
+    inc hl
+    ret
+
+    ds $fe - @, 0
+
+; unmapping the boot ROM is the last thing it does, the next instruction
+; is the cartridge's at $0100
+Done:
+    ldh [$50], a
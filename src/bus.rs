@@ -0,0 +1,75 @@
+use crate::interrupt::Interrupt;
+
+/// Everything the CPU is wired to. `gb::System` is the real thing, memory plus
+/// the hardware clocked by bus cycles, other implementations can stand in for
+/// it without touching the instruction code.
+pub trait Bus {
+    fn read8(&self, address: u16) -> u8;
+
+    fn write8(&mut self, address: u16, value: u8);
+
+    /// Advance the hardware clocked alongside the CPU by `cycles` T-cycles
+    fn tick(&mut self, cycles: u16);
+
+    /// Interrupts that are both requested (IF) and enabled (IE)
+    fn pending_interrupts(&self) -> u8 {
+        self.read8(0xffff) & self.read8(0xff0f) & 0x1f
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        let requested = self.read8(0xff0f);
+        self.write8(0xff0f, requested & !(interrupt as u8));
+    }
+
+    /// Low nibble of P1, a bit is 0 while a selected button is held
+    fn joypad_lines(&self) -> u8 {
+        0x0f
+    }
+
+    /// Called on STOP, returns whether it switched CGB speed instead of
+    /// stopping the CPU
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    /// ROM bank mapped at $4000-$7fff
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Whether the boot ROM is still mapped over $0000-$00ff
+    fn boot_rom_mapped(&self) -> bool {
+        false
+    }
+}
+
+/// 64 KiB of RAM with nothing else behind it, for running the CPU in isolation
+#[cfg(test)]
+pub struct FlatBus {
+    pub data: [u8; 0x10000],
+}
+
+#[cfg(test)]
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus { data: [0; 0x10000] }
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatBus {
+    fn read8(&self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        self.data[address as usize] = value;
+    }
+
+    fn tick(&mut self, _cycles: u16) {}
+
+    fn pending_interrupts(&self) -> u8 {
+        // nothing is wired to the interrupt lines
+        0
+    }
+}
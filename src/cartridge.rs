@@ -0,0 +1,192 @@
+//! The cartridge: ROM, optional external RAM and the mapper (MBC) that
+//! decides which parts of them the CPU sees.
+
+mod header;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rom_only;
+mod rtc;
+
+pub use header::CartridgeHeader;
+use rtc::RTC;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bank switching hardware on the cartridge. Writes to $0000-$7fff go to its
+/// registers, $a000-$bfff is external RAM (or whatever the mapper puts there).
+pub trait Mapper {
+    /// Read from $0000-$7fff
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+
+    fn write_register(&mut self, address: u16, value: u8);
+
+    /// Read from $a000-$bfff
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8;
+
+    /// Write to $a000-$bfff, returns whether anything that's saved changed
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool;
+
+    /// ROM bank mapped at $4000-$7fff
+    fn rom_bank(&self) -> usize;
+
+    /// Whether a rumble cart's motor is on
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    fn rtc(&self) -> Option<&RTC> {
+        None
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut RTC> {
+        None
+    }
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    /// A mapped file or bytes in memory
+    rom: Box<dyn AsRef<[u8]>>,
+    ram: Vec<u8>,
+    mapper: Box<dyn Mapper>,
+    /// RAM was written since it was last saved
+    pub ram_dirty: bool,
+}
+
+impl Cartridge {
+    pub fn new(rom: Box<dyn AsRef<[u8]>>) -> Result<Cartridge, String> {
+        let header = CartridgeHeader::parse((*rom).as_ref())?;
+        let rom_banks = header.rom_banks()
+            .ok_or_else(|| format!("Unknown ROM size ${:02x}", header.rom_size))?;
+        let ram_size = match header.cartridge_type {
+            // MBC2 has its RAM built in and the header says there's none
+            0x05 | 0x06 => mbc2::RAM_SIZE,
+            _ => header.ram_bytes().ok_or_else(|| format!("Unknown RAM size ${:02x}", header.ram_size))?,
+        };
+        let mapper: Box<dyn Mapper> = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Box::new(rom_only::RomOnly::new()),
+            0x01..=0x03 => Box::new(mbc1::MBC1::new(rom_banks, mbc1::is_multicart((*rom).as_ref()))),
+            0x05 | 0x06 => Box::new(mbc2::MBC2::new(rom_banks)),
+            0x0f | 0x10 => Box::new(mbc3::MBC3::new(rom_banks, true)),
+            0x11..=0x13 => Box::new(mbc3::MBC3::new(rom_banks, false)),
+            0x19..=0x1b => Box::new(mbc5::MBC5::new(rom_banks, false)),
+            0x1c..=0x1e => Box::new(mbc5::MBC5::new(rom_banks, true)),
+            n => return Err(format!("Unhandled cartridge type 0x{:02X}", n)),
+        };
+        Ok(Cartridge { header, rom, ram: vec![0; ram_size], mapper, ram_dirty: false })
+    }
+
+    /// A 32 KiB cartridge of type `kind` with RAM size byte `ram_size`,
+    /// running `code` at $0100
+    #[cfg(test)]
+    pub fn with_code(kind: u8, ram_size: u8, code: &[u8]) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = kind;
+        rom[0x0149] = ram_size;
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        Cartridge::new(Box::new(rom)).unwrap()
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mapper.read_rom((*self.rom).as_ref(), address)
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        self.mapper.write_register(address, value);
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mapper.read_ram(&self.ram, address)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        if self.mapper.write_ram(&mut self.ram, address, value) {
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        self.mapper.rom_bank()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+
+    /// RAM (and the clock) keep their contents while the power is off
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery()
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.mapper.rtc().is_some()
+    }
+
+    /// Advance the clock, if there is one, by `cycles` normal speed T-cycles
+    pub fn tick_rtc(&mut self, cycles: u64) {
+        if let Some(rtc) = self.mapper.rtc_mut() {
+            rtc.tick(cycles);
+        }
+    }
+
+    /// Contents of a .sav file: the RAM, followed by the RTC state if the
+    /// cartridge has a clock
+    pub fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mapper.rtc() {
+            data.extend(rtc.to_footer(unix_time()));
+        }
+        data
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_size = self.ram.len().min(data.len());
+        self.ram[..ram_size].copy_from_slice(&data[..ram_size]);
+        let footer = &data[ram_size..];
+        if let Some(rtc) = self.mapper.rtc_mut() {
+            if footer.len() >= rtc::FOOTER_SIZE - 4 {
+                rtc.load_footer(footer, unix_time());
+            }
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/// Byte of `bank` for an address in either ROM window. Short dumps read as
+/// open bus instead of panicking.
+fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    rom.get(bank * 0x4000 + (address & 0x3fff) as usize).copied().unwrap_or(0xff)
+}
+
+/// Offset into external RAM of `bank` for an address in $a000-$bfff, None
+/// when there's no RAM. Smaller RAM is mirrored.
+fn ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * 0x2000 + (address - 0xa000) as usize) % ram.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stored_ram_writes_need_saving() {
+        let mut cartridge = Cartridge::with_code(0x03, 0x02, &[]);
+        cartridge.write_ram(0xa000, 0x42);
+        assert!(!cartridge.ram_dirty);
+        cartridge.write_register(0x0000, 0x0a);
+        cartridge.write_ram(0xa000, 0x42);
+        assert!(cartridge.ram_dirty);
+
+        let mut no_ram = Cartridge::with_code(0x01, 0x00, &[]);
+        no_ram.write_register(0x0000, 0x0a);
+        no_ram.write_ram(0xa000, 0x42);
+        assert!(!no_ram.ram_dirty);
+    }
+}
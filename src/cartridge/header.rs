@@ -0,0 +1,450 @@
+//! Cartridge header at $0100-$014f.
+
+pub struct CartridgeHeader {
+    /// Up to 16 characters, fewer on CGB games that use the end of the field
+    /// for their CGB flag and manufacturer code
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    /// $80 for games with CGB functions, $c0 for CGB only games
+    pub cgb_flag: u8,
+    pub sgb: bool,
+    /// $33 means the publisher is in `new_licensee` instead
+    pub old_licensee: u8,
+    pub new_licensee: String,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    /// 0 for Japan, 1 for everywhere else
+    pub destination: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, String> {
+        if rom.len() < 0x0150 {
+            return Err(format!("ROM is too small to have a header: {} bytes", rom.len()));
+        }
+        let cgb_flag = rom[0x0143];
+        let manufacturer_code = &rom[0x013f..0x0143];
+        let has_manufacturer_code = cgb_flag & 0x80 != 0
+            && manufacturer_code.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+        let title_end = if has_manufacturer_code {
+            0x013f
+        } else if cgb_flag & 0x80 != 0 {
+            0x0143
+        } else {
+            0x0144
+        };
+
+        let computed_header_checksum = rom[0x0134..=0x014c]
+            .iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(offset, _)| offset != 0x014e && offset != 0x014f)
+            .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16));
+
+        Ok(CartridgeHeader {
+            title: decode_text(&rom[0x0134..title_end]),
+            manufacturer_code: if has_manufacturer_code { Some(decode_text(manufacturer_code)) } else { None },
+            cgb_flag,
+            sgb: rom[0x0146] == 0x03,
+            old_licensee: rom[0x014b],
+            new_licensee: decode_text(&rom[0x0144..0x0146]),
+            cartridge_type: rom[0x0147],
+            rom_size: rom[0x0148],
+            ram_size: rom[0x0149],
+            destination: rom[0x014a],
+            version: rom[0x014c],
+            header_checksum: rom[0x014d],
+            global_checksum: (rom[0x014e] as u16) << 8 | rom[0x014f] as u16,
+            computed_header_checksum,
+            computed_global_checksum,
+        })
+    }
+
+    /// The boot ROM locks up when this is wrong
+    pub fn header_checksum_ok(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn computed_header_checksum(&self) -> u8 {
+        self.computed_header_checksum
+    }
+
+    /// Nothing checks this one, plenty of homebrew gets it wrong
+    pub fn global_checksum_ok(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn computed_global_checksum(&self) -> u16 {
+        self.computed_global_checksum
+    }
+
+    /// 16 KiB ROM banks, None for a size byte we don't know
+    pub fn rom_banks(&self) -> Option<usize> {
+        match self.rom_size {
+            n @ 0..=8 => Some(2 << n),
+            _ => None,
+        }
+    }
+
+    /// External RAM in bytes, not counting RAM built into the mapper, None
+    /// for a size byte we don't know
+    pub fn ram_bytes(&self) -> Option<usize> {
+        let bytes = match self.ram_size {
+            0 => 0,
+            1 => 0x800,
+            2 => 0x2000,
+            3 => 0x8000,
+            4 => 0x20000,
+            5 => 0x10000,
+            _ => return None,
+        };
+        Some(bytes)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    }
+
+    pub fn cartridge_type_name(&self) -> Option<&'static str> {
+        let name = match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Publisher, from whichever licensee code is in use
+    pub fn licensee_name(&self) -> Option<&'static str> {
+        if self.old_licensee == 0x33 {
+            new_licensee_name(&self.new_licensee)
+        } else {
+            old_licensee_name(self.old_licensee)
+        }
+    }
+}
+
+/// Header text up to the first NUL, anything that isn't printable ASCII as '?'
+fn decode_text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    let name = match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "b-ai",
+        "20" => "KSS",
+        "22" => "POW",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco Japan",
+        "29" => "SETA",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean/Acclaim",
+        "34" => "Konami",
+        "35" => "Hector",
+        "37" => "Taito",
+        "38" => "Hudson",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu",
+        "46" => "Angel",
+        "47" => "Bullet-Proof",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "53" => "American Sammy",
+        "54" => "Konami",
+        "55" => "Hi Tech Entertainment",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus",
+        "61" => "Virgin",
+        "64" => "LucasArts",
+        "67" => "Ocean",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "Sculptured",
+        "75" => "SCi",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa",
+        "83" => "LOZC",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video System",
+        "93" => "Ocean/Acclaim",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "A4" => "Konami",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "Hot-B",
+        0x0a => "Jaleco",
+        0x0b => "Coconuts Japan",
+        0x0c => "Elite Systems",
+        0x13 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1a => "Yanoman",
+        0x1d => "Japan Clary",
+        0x1f => "Virgin",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "SETA",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3c => "Entertainment Interactive",
+        0x3e => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 => "Atlus",
+        0x44 => "Malibu",
+        0x46 => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4a => "Virgin",
+        0x4d => "Malibu",
+        0x4f => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "Sammy USA",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5a => "Mindscape",
+        0x5b => "Romstar",
+        0x5c => "Naxat Soft",
+        0x5d => "Tradewest",
+        0x60 => "Titus",
+        0x61 => "Virgin",
+        0x67 => "Ocean",
+        0x69 => "Electronic Arts",
+        0x6e => "Elite Systems",
+        0x6f => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay",
+        0x72 => "Broderbund",
+        0x73 => "Sculptured Soft",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7a => "Triffix Entertainment",
+        0x7c => "MicroProse",
+        0x7f => "Kemco",
+        0x80 => "Misawa",
+        0x83 => "LOZC",
+        0x86 => "Tokuma Shoten",
+        0x8b => "Bullet-Proof",
+        0x8c => "Vic Tokai",
+        0x8e => "Ape",
+        0x8f => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9a => "Nihon Bussan",
+        0x9b => "Tecmo",
+        0x9c => "Imagineer",
+        0x9d => "Banpresto",
+        0x9f => "Nova",
+        0xa1 => "Hori Electric",
+        0xa2 => "Bandai",
+        0xa4 => "Konami",
+        0xa6 => "Kawada",
+        0xa7 => "Takara",
+        0xa9 => "Technos Japan",
+        0xaa => "Broderbund",
+        0xac => "Toei Animation",
+        0xad => "Toho",
+        0xaf => "Namco",
+        0xb0 => "Acclaim",
+        0xb1 => "ASCII or Nexsoft",
+        0xb2 => "Bandai",
+        0xb4 => "Square Enix",
+        0xb6 => "HAL Laboratory",
+        0xb7 => "SNK",
+        0xb9 => "Pony Canyon",
+        0xba => "Culture Brain",
+        0xbb => "Sunsoft",
+        0xbd => "Sony Imagesoft",
+        0xbf => "Sammy",
+        0xc0 => "Taito",
+        0xc2 => "Kemco",
+        0xc3 => "Square",
+        0xc4 => "Tokuma Shoten",
+        0xc5 => "Data East",
+        0xc6 => "Tonkin House",
+        0xc8 => "Koei",
+        0xc9 => "UFL",
+        0xca => "Ultra",
+        0xcb => "Vap",
+        0xcc => "Use",
+        0xcd => "Meldac",
+        0xce => "Pony Canyon",
+        0xcf => "Angel",
+        0xd0 => "Taito",
+        0xd1 => "Sofel",
+        0xd2 => "Quest",
+        0xd3 => "Sigma Enterprises",
+        0xd4 => "ASK Kodansha",
+        0xd6 => "Naxat Soft",
+        0xd7 => "Copya System",
+        0xd9 => "Banpresto",
+        0xda => "Tomy",
+        0xdb => "LJN",
+        0xdd => "NCS",
+        0xde => "Human",
+        0xdf => "Altron",
+        0xe0 => "Jaleco",
+        0xe1 => "Towa Chiki",
+        0xe2 => "Yutaka",
+        0xe3 => "Varie",
+        0xe5 => "Epoch",
+        0xe7 => "Athena",
+        0xe8 => "Asmik Ace",
+        0xe9 => "Natsume",
+        0xea => "King Records",
+        0xeb => "Atlus",
+        0xec => "Epic/Sony Records",
+        0xee => "IGS",
+        0xf0 => "A Wave",
+        0xf3 => "Extreme Entertainment",
+        0xff => "LJN",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `title` runs into the CGB flag at $0143 when it's 16 bytes long
+    fn rom_with_header(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014b] = 0x01;
+        rom
+    }
+
+    #[test]
+    fn title_needs_no_terminator() {
+        let header = CartridgeHeader::parse(&rom_with_header(b"SIXTEEN CHARS!!!")).unwrap();
+        assert_eq!(header.title, "SIXTEEN CHARS!!!");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.licensee_name(), Some("Nintendo"));
+    }
+
+    #[test]
+    fn cgb_title_stops_before_manufacturer_code() {
+        let header = CartridgeHeader::parse(&rom_with_header(b"POKEMON\0\0\0\0AAUE\x80")).unwrap();
+        assert_eq!(header.title, "POKEMON");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAUE"));
+    }
+
+    #[test]
+    fn non_ascii_title_is_replaced() {
+        let header = CartridgeHeader::parse(&rom_with_header(&[b'A', 0xff, b'B'])).unwrap();
+        assert_eq!(header.title, "A?B");
+    }
+
+    #[test]
+    fn checksums() {
+        let mut rom = rom_with_header(b"TEST");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_ok());
+        rom[0x014d] = header.computed_header_checksum();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_ok());
+        let global = header.computed_global_checksum();
+        rom[0x014e] = (global >> 8) as u8;
+        rom[0x014f] = global as u8;
+        assert!(CartridgeHeader::parse(&rom).unwrap().global_checksum_ok());
+    }
+
+    #[test]
+    fn short_roms_are_errors_and_unknown_sizes_are_none() {
+        assert!(CartridgeHeader::parse(&[0; 100]).is_err());
+        let mut rom = rom_with_header(b"TEST");
+        rom[0x0148] = 0x52;
+        rom[0x0149] = 0x06;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.rom_banks(), None);
+        assert_eq!(header.ram_bytes(), None);
+    }
+}
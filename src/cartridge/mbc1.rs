@@ -0,0 +1,148 @@
+use super::{ram_offset, read_rom_bank, Mapper};
+
+/// MBC1, up to 2 MiB of ROM and 32 KiB of RAM. MBC1M multicarts wire BANK2
+/// one bit lower so it selects one of four 256 KiB games.
+pub struct MBC1 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    /// 5 bits, selects the ROM bank at $4000-$7fff
+    bank1: u8,
+    /// 2 bits, upper ROM bank bits or the RAM bank
+    bank2: u8,
+    /// Mode 1 applies BANK2 to $0000-$3fff and $a000-$bfff as well
+    mode: bool,
+    multicart: bool,
+}
+
+impl MBC1 {
+    pub fn new(rom_banks: usize, multicart: bool) -> MBC1 {
+        MBC1 { rom_banks, ram_enabled: false, bank1: 1, bank2: 0, mode: false, multicart }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    /// ROM bank at $0000-$3fff
+    fn rom_bank0(&self) -> usize {
+        if self.mode {
+            ((self.bank2 as usize) << self.bank2_shift()) & (self.rom_banks - 1)
+        } else {
+            0
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode { self.bank2 as usize } else { 0 }
+    }
+}
+
+impl Mapper for MBC1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if address < 0x4000 { self.rom_bank0() } else { self.rom_bank() };
+        read_rom_bank(rom, bank, address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                // bank 0 can't be selected here, it reads as bank 1
+                self.bank1 = value & 0x1f;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5fff => self.bank2 = value & 0x03,
+            0x6000..=0x7fff => self.mode = value & 0x01 != 0,
+            _ => panic!("Not an MBC1 register: 0x{:04X}", address),
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        // the bank 0 check above sees all 5 bits even though MBC1M only wires 4
+        let bank1 = if self.multicart { self.bank1 & 0x0f } else { self.bank1 };
+        ((self.bank2 as usize) << self.bank2_shift() | bank1 as usize) & (self.rom_banks - 1)
+    }
+}
+
+/// MBC1M multicarts aren't marked in the header, but they're all 1 MiB and
+/// every 256 KiB game has its own header with the Nintendo logo
+pub fn is_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[0x40104..0x40134] == rom[0x0104..0x0134]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut mbc1 = MBC1::new(128, false);
+        mbc1.write_register(0x2000, 0x00);
+        assert_eq!(mbc1.rom_bank(), 1);
+        mbc1.write_register(0x2000, 0x20);
+        assert_eq!(mbc1.rom_bank(), 1);
+        mbc1.write_register(0x4000, 0x01);
+        assert_eq!(mbc1.rom_bank(), 0x21);
+    }
+
+    #[test]
+    fn banks_are_masked_to_rom_size() {
+        let mut mbc1 = MBC1::new(8, false);
+        mbc1.write_register(0x2000, 0x1b);
+        assert_eq!(mbc1.rom_bank(), 3);
+    }
+
+    #[test]
+    fn mode_1_banks_lower_rom_and_ram() {
+        let mut mbc1 = MBC1::new(128, false);
+        mbc1.write_register(0x4000, 0x02);
+        assert_eq!(mbc1.rom_bank0(), 0);
+        assert_eq!(mbc1.ram_bank(), 0);
+        mbc1.write_register(0x6000, 0x01);
+        assert_eq!(mbc1.rom_bank0(), 0x40);
+        assert_eq!(mbc1.ram_bank(), 2);
+    }
+
+    #[test]
+    fn ram_reads_ff_while_disabled() {
+        let mut mbc1 = MBC1::new(4, false);
+        let mut ram = vec![0; 0x2000];
+        mbc1.write_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(mbc1.read_ram(&ram, 0xa000), 0xff);
+        mbc1.write_register(0x0000, 0x0a);
+        mbc1.write_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(mbc1.read_ram(&ram, 0xa000), 0x12);
+    }
+
+    #[test]
+    fn mbc1m_wires_bank2_to_bit_4() {
+        let mut mbc1 = MBC1::new(64, true);
+        mbc1.write_register(0x2000, 0x12);
+        mbc1.write_register(0x4000, 0x03);
+        assert_eq!(mbc1.rom_bank(), 0x32);
+        // $10 isn't 0 so it isn't bumped, but only the low 4 bits reach the ROM
+        mbc1.write_register(0x2000, 0x10);
+        assert_eq!(mbc1.rom_bank(), 0x30);
+        mbc1.write_register(0x6000, 0x01);
+        assert_eq!(mbc1.rom_bank0(), 0x30);
+    }
+}
@@ -0,0 +1,85 @@
+use super::{read_rom_bank, Mapper};
+
+/// MBC2, up to 256 KiB of ROM and 512 4-bit cells of built-in RAM. Address
+/// bit 8 picks the register, so both live anywhere in $0000-$3fff.
+pub struct MBC2 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: usize,
+}
+
+/// Built-in RAM cells, stored one per byte like other emulators' .sav files
+pub const RAM_SIZE: usize = 512;
+
+impl MBC2 {
+    pub fn new(rom_banks: usize) -> MBC2 {
+        MBC2 { rom_banks, ram_enabled: false, rom_bank: 1 }
+    }
+}
+
+impl Mapper for MBC2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(rom, bank, address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3fff if address & 0x0100 == 0 => self.ram_enabled = value & 0x0f == 0x0a,
+            0x0000..=0x3fff => {
+                // bank 0 reads as bank 1
+                let bank = (value & 0x0f).max(1) as usize;
+                self.rom_bank = bank & (self.rom_banks - 1);
+            }
+            0x4000..=0x7fff => {}
+            _ => panic!("Not an MBC2 register: 0x{:04X}", address),
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        // only the low nibble exists, the rest of the bus floats high
+        ram[(address as usize) % RAM_SIZE] | 0xf0
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ram_enabled {
+            ram[(address as usize) % RAM_SIZE] = value & 0x0f;
+        }
+        self.ram_enabled
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut mbc2 = MBC2::new(16);
+        mbc2.write_register(0x2100, 0x0a);
+        assert!(!mbc2.ram_enabled);
+        assert_eq!(mbc2.rom_bank(), 0x0a);
+        mbc2.write_register(0x3e00, 0x0a);
+        assert!(mbc2.ram_enabled);
+        mbc2.write_register(0x0100, 0x00);
+        assert_eq!(mbc2.rom_bank(), 1);
+    }
+
+    #[test]
+    fn ram_is_4_bits_and_mirrored() {
+        let mut mbc2 = MBC2::new(16);
+        let mut ram = vec![0; RAM_SIZE];
+        mbc2.write_register(0x0000, 0x0a);
+        mbc2.write_ram(&mut ram, 0xa005, 0x3c);
+        assert_eq!(mbc2.read_ram(&ram, 0xa005), 0xfc);
+        assert_eq!(mbc2.read_ram(&ram, 0xa205), 0xfc);
+        assert_eq!(mbc2.read_ram(&ram, 0xbe05), 0xfc);
+    }
+}
@@ -0,0 +1,131 @@
+use super::rtc::RTC;
+use super::{ram_offset, read_rom_bank, Mapper};
+
+/// MBC3, up to 2 MiB of ROM, 32 KiB of RAM and optionally a real-time clock
+/// whose registers are mapped in place of a RAM bank
+pub struct MBC3 {
+    rom_banks: usize,
+    /// Enables both RAM and the RTC registers
+    ram_enabled: bool,
+    rom_bank: usize,
+    /// $00-$03 selects a RAM bank, $08-$0c an RTC register
+    ram_select: u8,
+    rtc: Option<RTC>,
+}
+
+impl MBC3 {
+    pub fn new(rom_banks: usize, has_rtc: bool) -> MBC3 {
+        MBC3 {
+            rom_banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc: if has_rtc { Some(RTC::new()) } else { None },
+        }
+    }
+
+    fn selected_rtc_register(&self) -> Option<u8> {
+        match self.ram_select {
+            0x08..=0x0c if self.rtc.is_some() => Some(self.ram_select),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for MBC3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(rom, bank, address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                // bank 0 reads as bank 1, like on MBC1
+                let bank = (value & 0x7f).max(1) as usize;
+                self.rom_bank = bank & (self.rom_banks - 1);
+            }
+            0x4000..=0x5fff => self.ram_select = value & 0x0f,
+            0x6000..=0x7fff => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+            _ => panic!("Not an MBC3 register: 0x{:04X}", address),
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        if let Some(register) = self.selected_rtc_register() {
+            return self.rtc.as_ref().unwrap().read(register);
+        }
+        match self.ram_select {
+            0x00..=0x03 => ram_offset(ram, self.ram_select as usize, address).map_or(0xff, |offset| ram[offset]),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        if let Some(register) = self.selected_rtc_register() {
+            // the clock is saved along with the RAM
+            self.rtc.as_mut().unwrap().write(register, value);
+            return true;
+        }
+        match ram_offset(ram, self.ram_select as usize, address) {
+            Some(offset) if self.ram_select <= 0x03 => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn rtc(&self) -> Option<&RTC> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut RTC> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtc_registers_replace_ram() {
+        let mut mbc3 = MBC3::new(128, true);
+        let mut ram = vec![0; 0x8000];
+        mbc3.write_register(0x0000, 0x0a);
+        mbc3.write_register(0x4000, 0x01);
+        mbc3.write_ram(&mut ram, 0xa000, 0x12);
+        mbc3.write_register(0x4000, 0x09);
+        mbc3.write_ram(&mut ram, 0xa000, 0x2a);
+        mbc3.write_register(0x6000, 0x00);
+        mbc3.write_register(0x6000, 0x01);
+        assert_eq!(mbc3.read_ram(&ram, 0xa000), 0xea);
+        mbc3.write_register(0x4000, 0x01);
+        assert_eq!(mbc3.read_ram(&ram, 0xa000), 0x12);
+    }
+
+    #[test]
+    fn seven_bit_rom_bank() {
+        let mut mbc3 = MBC3::new(128, false);
+        mbc3.write_register(0x2000, 0x00);
+        assert_eq!(mbc3.rom_bank(), 1);
+        mbc3.write_register(0x2000, 0x40);
+        assert_eq!(mbc3.rom_bank(), 0x40);
+    }
+}
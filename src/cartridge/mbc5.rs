@@ -0,0 +1,99 @@
+use super::{ram_offset, read_rom_bank, Mapper};
+
+/// MBC5, up to 8 MiB of ROM and 128 KiB of RAM. On rumble carts bit 3 of the
+/// RAM bank register drives the motor instead of selecting a bank.
+pub struct MBC5 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    /// 9 bits, unlike MBC1 and MBC3 bank 0 can be mapped at $4000-$7fff too
+    rom_bank: usize,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(rom_banks: usize, has_rumble: bool) -> MBC5 {
+        MBC5 { rom_banks, ram_enabled: false, rom_bank: 1, ram_bank: 0, has_rumble, rumble: false }
+    }
+}
+
+impl Mapper for MBC5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank & (self.rom_banks - 1) };
+        read_rom_bank(rom, bank, address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = value == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | value as usize,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((value as usize & 0x01) << 8),
+            0x4000..=0x5fff => {
+                if self.has_rumble {
+                    self.rumble = value & 0x08 != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0f;
+                }
+            }
+            0x6000..=0x7fff => {}
+            _ => panic!("Not an MBC5 register: 0x{:04X}", address),
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) if self.ram_enabled => ram[offset],
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) if self.ram_enabled => {
+                ram[offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank & (self.rom_banks - 1)
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nine_bit_rom_bank_including_0() {
+        let mut mbc5 = MBC5::new(512, false);
+        mbc5.write_register(0x2000, 0x00);
+        assert_eq!(mbc5.rom_bank(), 0);
+        mbc5.write_register(0x3000, 0x01);
+        mbc5.write_register(0x2000, 0x23);
+        assert_eq!(mbc5.rom_bank(), 0x123);
+        mbc5.write_register(0x3000, 0x00);
+        assert_eq!(mbc5.rom_bank(), 0x23);
+    }
+
+    #[test]
+    fn rumble_bit_isnt_a_ram_bank() {
+        let mut mbc5 = MBC5::new(64, true);
+        let mut ram = vec![0; 0x8000];
+        mbc5.write_register(0x0000, 0x0a);
+        mbc5.write_register(0x4000, 0x09);
+        assert!(mbc5.rumble());
+        mbc5.write_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(ram[0x2000], 0x12);
+        mbc5.write_register(0x4000, 0x01);
+        assert!(!mbc5.rumble());
+    }
+}
@@ -0,0 +1,36 @@
+use super::{ram_offset, read_rom_bank, Mapper};
+
+/// 32 KiB of ROM wired straight to the bus, with up to one bank of RAM
+pub struct RomOnly;
+
+impl RomOnly {
+    pub fn new() -> RomOnly {
+        RomOnly
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        read_rom_bank(rom, (address >> 14) as usize, address)
+    }
+
+    fn write_register(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        ram_offset(ram, 0, address).map_or(0xff, |offset| ram[offset])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_offset(ram, 0, address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+}
@@ -0,0 +1,247 @@
+//! MBC3 real-time clock. It counts from its own 32768 Hz crystal, here it's
+//! advanced by emulated time while running and by host time between runs.
+
+/// Normal speed T-cycles per second
+pub const CYCLES_PER_SECOND: u64 = 4194304;
+
+/// Size of the footer appended to the RAM in .sav files, the layout BGB and
+/// VBA-M use: live then latched registers as 32-bit words, then a 64-bit
+/// UNIX timestamp, all little endian
+pub const FOOTER_SIZE: usize = 48;
+
+#[derive(Clone, Copy, Default)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days_low: u8,
+    /// Bit 0 is bit 8 of the day counter, bit 6 halts the clock and bit 7 is
+    /// the sticky day counter carry
+    days_high: u8,
+}
+
+impl Registers {
+    fn days(&self) -> u64 {
+        ((self.days_high as u64 & 0x01) << 8) | self.days_low as u64
+    }
+
+    fn set_days(&mut self, days: u64) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & 0xfe) | ((days >> 8) as u8 & 0x01);
+    }
+
+    fn halted(&self) -> bool {
+        self.days_high & 0x40 != 0
+    }
+
+    /// Counting up from a value written out of range wraps at the register
+    /// width without carrying into the next one
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.advance_days(1);
+    }
+
+    fn advance_days(&mut self, days: u64) {
+        let days = self.days() + days;
+        if days >= 512 {
+            self.days_high |= 0x80;
+        }
+        self.set_days(days % 512);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        while seconds > 0 && !self.in_range() {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let time = ((self.hours as u64 * 60) + self.minutes as u64) * 60 + self.seconds as u64 + seconds;
+        self.seconds = (time % 60) as u8;
+        self.minutes = (time / 60 % 60) as u8;
+        self.hours = (time / 3600 % 24) as u8;
+        self.advance_days(time / 86400);
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds | 0xc0,
+            0x09 => self.minutes | 0xc0,
+            0x0a => self.hours | 0xe0,
+            0x0b => self.days_low,
+            0x0c => self.days_high | 0x3e,
+            _ => panic!("Not an RTC register: 0x{:02X}", register),
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => self.seconds = value & 0x3f,
+            0x09 => self.minutes = value & 0x3f,
+            0x0a => self.hours = value & 0x1f,
+            0x0b => self.days_low = value,
+            0x0c => self.days_high = value & 0xc1,
+            _ => panic!("Not an RTC register: 0x{:02X}", register),
+        }
+    }
+
+    fn to_words(self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.days_low, self.days_high]
+    }
+
+    fn from_words(words: [u8; 5]) -> Registers {
+        let mut registers = Registers::default();
+        for (register, &value) in (0x08..=0x0c).zip(words.iter()) {
+            registers.write(register, value);
+        }
+        registers
+    }
+}
+
+pub struct RTC {
+    live: Registers,
+    /// What the CPU reads, copied from `live` on a latch
+    latched: Registers,
+    /// Normal speed T-cycles into the current second
+    subsecond: u64,
+    /// Last value written to the latch register
+    latch: u8,
+}
+
+impl RTC {
+    pub fn new() -> RTC {
+        RTC { live: Registers::default(), latched: Registers::default(), subsecond: 0, latch: 0xff }
+    }
+
+    /// Advance by `cycles` normal speed T-cycles
+    pub fn tick(&mut self, cycles: u64) {
+        if self.live.halted() {
+            return;
+        }
+        self.subsecond += cycles;
+        self.live.advance(self.subsecond / CYCLES_PER_SECOND);
+        self.subsecond %= CYCLES_PER_SECOND;
+    }
+
+    /// Writing 0 then 1 copies the clock into the readable registers
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch == 0x00 && value == 0x01 {
+            self.latched = self.live;
+        }
+        self.latch = value;
+    }
+
+    /// Read register $08-$0c
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched.read(register)
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        if register == 0x08 {
+            // writing the seconds restarts the current second
+            self.subsecond = 0;
+        }
+        self.live.write(register, value);
+    }
+
+    pub fn to_footer(&self, timestamp: u64) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        for &word in self.live.to_words().iter().chain(self.latched.to_words().iter()) {
+            footer.extend_from_slice(&(word as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    /// Restore from a .sav footer and catch up with the time that passed
+    /// since it was written. Some emulators write a 32-bit timestamp, making
+    /// the footer 44 bytes.
+    pub fn load_footer(&mut self, footer: &[u8], now: u64) {
+        let word = |i: usize| footer[i * 4];
+        self.live = Registers::from_words([word(0), word(1), word(2), word(3), word(4)]);
+        self.latched = Registers::from_words([word(5), word(6), word(7), word(8), word(9)]);
+        let mut timestamp = [0; 8];
+        let timestamp_bytes = &footer[40..footer.len().min(48)];
+        timestamp[..timestamp_bytes.len()].copy_from_slice(timestamp_bytes);
+        let timestamp = u64::from_le_bytes(timestamp);
+        if !self.live.halted() {
+            self.live.advance(now.saturating_sub(timestamp));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latched(rtc: &mut RTC) -> [u8; 5] {
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        [rtc.read(0x08), rtc.read(0x09), rtc.read(0x0a), rtc.read(0x0b), rtc.read(0x0c)]
+    }
+
+    #[test]
+    fn reads_are_latched() {
+        let mut rtc = RTC::new();
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        rtc.tick(CYCLES_PER_SECOND * 3);
+        assert_eq!(rtc.read(0x08) & 0x3f, 0);
+        assert_eq!(latched(&mut rtc)[0] & 0x3f, 3);
+    }
+
+    #[test]
+    fn carries_into_days_and_sets_day_carry() {
+        let mut rtc = RTC::new();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0a, 23);
+        rtc.write(0x0b, 0xff);
+        rtc.write(0x0c, 0x01);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latched(&mut rtc), [0xc0, 0xc0, 0xe0, 0x00, 0xbe]);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_without_carry() {
+        let mut rtc = RTC::new();
+        rtc.write(0x08, 63);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latched(&mut rtc)[..2], [0xc0, 0xc0]);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut rtc = RTC::new();
+        rtc.write(0x0c, 0x40);
+        rtc.tick(CYCLES_PER_SECOND * 10);
+        assert_eq!(latched(&mut rtc)[0], 0xc0);
+    }
+
+    #[test]
+    fn footer_catches_up_with_host_time() {
+        let mut rtc = RTC::new();
+        rtc.write(0x09, 30);
+        let footer = rtc.to_footer(1000);
+        assert_eq!(footer.len(), FOOTER_SIZE);
+        let mut loaded = RTC::new();
+        loaded.load_footer(&footer, 1000 + 2 * 86400 + 3600 + 45 * 60 + 5);
+        assert_eq!(latched(&mut loaded), [0xc5, 0xcf, 0xe2, 0x02, 0x3e]);
+    }
+}
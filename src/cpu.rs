@@ -1,11 +1,8 @@
-use crate::memory::Memory;
-
-#[inline]
-fn set_flag_if(flags: &mut u8, mask: u8, condition: bool) {
-    if condition {
-        *flags |= mask;
-    } 
-}
+use crate::bus::Bus;
+use crate::interrupt::{Interrupt, InterruptController};
+use crate::opcodes;
+use crate::trace::Tracer;
+use std::fmt;
 
 #[inline]
 fn set_flag(flags: &mut u8, mask: u8, set: bool) {
@@ -16,19 +13,34 @@ fn set_flag(flags: &mut u8, mask: u8, set: bool) {
     }
 }
 
-static TAC_SELECT: [u16; 4] = [1024, 16, 64, 256];
-
 static FLAG_Z: u8 = 0x80;
 static FLAG_N: u8 = 0x40;
 static FLAG_H: u8 = 0x20;
 static FLAG_C: u8 = 0x10;
 
-pub enum Interrupt {
-    VBlank = 0x01,
-    LCDC = 0x02,
-    Timer = 0x04,
-    Serial = 0x08,
-    JoyPad = 0x10,
+/// How the rest of the system is advanced while the CPU runs
+pub enum Timing {
+    /// Once per instruction, after it has executed
+    Instruction,
+    /// On every bus access, so memory accesses see mid-instruction PPU and timer state
+    MCycle,
+}
+
+/// Something the CPU can't execute past, returned from `CPU::step`
+#[derive(Debug)]
+pub enum Fault {
+    /// One of the 11 unused opcodes, the CPU stays locked up until reset
+    IllegalOpcode { opcode: u8, address: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::IllegalOpcode { opcode, address } => {
+                write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}", opcode, address)
+            }
+        }
+    }
 }
 
 pub struct CPU {
@@ -37,11 +49,16 @@ pub struct CPU {
     pub reg: Registers,
 
     pub halt: bool,
-    pub interrupts: bool,
-    pub interrupt_enable: bool,
-    pub interrupt_flag: bool,
-    pub timer_cycles: u16,
-    pub divider_cycles: u16,
+    pub halt_bug: bool,
+    pub stopped: bool,
+    /// Hung by an illegal opcode, not even interrupts get it going again
+    pub locked: bool,
+    pub interrupts: InterruptController,
+
+    pub timing: Timing,
+    pub tracer: Option<Tracer>,
+    /// Cycles of the current instruction already ticked by bus accesses
+    ticked: u16,
 }
 
 #[derive(Default)]
@@ -63,11 +80,13 @@ impl CPU {
             sp: 0xfffe,
             reg: Default::default(),
             halt: false,
-            interrupts: false,
-            interrupt_enable: false,
-            interrupt_flag: false,
-            timer_cycles: 0,
-            divider_cycles: 0,
+            halt_bug: false,
+            stopped: false,
+            locked: false,
+            interrupts: InterruptController::new(),
+            timing: Timing::Instruction,
+            tracer: None,
+            ticked: 0,
         }
     }
 
@@ -135,50 +154,141 @@ impl CPU {
         self.reg.f & FLAG_C != 0
     }
 
-    pub fn set_interrupt(&mut self, mem: &mut Memory, interrupt: Interrupt) {
-        mem.data[0xff0f] |= interrupt as u8;
-    }
-
-    pub fn unset_interrupt(&mut self, mem: &mut Memory, interrupt: Interrupt) {
-        mem.data[0xff0f] &= !(interrupt as u8);
-    }
-
-    pub fn interrupt_reg(&self, mem: &Memory) -> u8 {
-        mem.data[0xffff]
+    /// SP plus a signed offset as in `add sp, n` and `ld hl, sp+n`, H and C
+    /// come from the unsigned addition of the low bytes
+    fn add_sp_offset(&mut self, offset: u8) -> u16 {
+        let half_carry = (self.sp & 0xf) + (offset as u16 & 0xf) > 0xf;
+        let carry = (self.sp & 0xff) + offset as u16 > 0xff;
+        self.set_flag(FLAG_Z, false);
+        self.set_flag(FLAG_N, false);
+        self.set_flag(FLAG_H, half_carry);
+        self.set_flag(FLAG_C, carry);
+        self.sp.wrapping_add(offset as i8 as u16)
     }
 
     // Stack
 
-    pub fn push_stack(&mut self, mem: &mut Memory, value: u16) {
-        self.sp -= 2;
-        mem.write16(self.sp, value);
+    pub fn push_stack<B: Bus>(&mut self, mem: &mut B, value: u16) {
+        // high byte goes first
+        self.sp -= 1;
+        self.write8(mem, self.sp, (value >> 8) as u8);
+        self.sp -= 1;
+        self.write8(mem, self.sp, value as u8);
     }
 
-    pub fn pop_stack(&mut self, mem: &mut Memory) -> u16 {
-        let result = mem.read16(self.sp);
+    pub fn pop_stack<B: Bus>(&mut self, mem: &mut B) -> u16 {
+        let result = self.read16(mem, self.sp);
         self.sp += 2;
         result
     }
 
     // Memory
 
-    pub fn fetch8(&mut self, mem: &mut Memory) -> u8 {
-        let result = mem.read8(self.pc);
-        self.pc += 1;
+    /// Every bus access takes one M-cycle, in M-cycle timing the rest of the
+    /// system is advanced right after it
+    fn read8<B: Bus>(&mut self, mem: &mut B, addr: u16) -> u8 {
+        let result = mem.read8(addr);
+        self.tick(mem);
+        result
+    }
+
+    fn write8<B: Bus>(&mut self, mem: &mut B, addr: u16, val: u8) {
+        mem.write8(addr, val);
+        self.tick(mem);
+    }
+
+    fn read16<B: Bus>(&mut self, mem: &mut B, addr: u16) -> u16 {
+        let low = self.read8(mem, addr) as u16;
+        let high = self.read8(mem, addr.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    fn write16<B: Bus>(&mut self, mem: &mut B, addr: u16, val: u16) {
+        self.write8(mem, addr, val as u8);
+        self.write8(mem, addr.wrapping_add(1), (val >> 8) as u8);
+    }
+
+    fn tick<B: Bus>(&mut self, mem: &mut B) {
+        if let Timing::MCycle = self.timing {
+            mem.tick(4);
+            self.ticked += 4;
+        }
+    }
+
+    /// Advance the system by whatever part of an instruction's cycles wasn't
+    /// already spent on bus accesses. Internal delays are lumped in at the end.
+    fn finish<B: Bus>(&mut self, mem: &mut B, cycles: u16) -> u16 {
+        let remaining = cycles.saturating_sub(self.ticked);
+        mem.tick(remaining);
+        self.ticked = 0;
+        cycles
+    }
+
+    pub fn fetch8<B: Bus>(&mut self, mem: &mut B) -> u8 {
+        let result = self.read8(mem, self.pc);
+        if self.halt_bug {
+            // the byte after HALT is read twice
+            self.halt_bug = false;
+        } else {
+            self.pc += 1;
+        }
         result
     }
 
-    pub fn fetch16(&mut self, mem: &mut Memory) -> u16 {
-        let result = mem.read16(self.pc);
+    pub fn fetch16<B: Bus>(&mut self, mem: &mut B) -> u16 {
+        let result = self.read16(mem, self.pc);
         self.pc += 2;
         result
     }
 
     // Execute
 
-    pub fn step(&mut self, mem: &mut Memory) -> u16 {
+    pub fn step<B: Bus>(&mut self, mem: &mut B) -> Result<u16, Fault> {
+        if self.locked {
+            return Ok(self.finish(mem, 4));
+        }
+
+        if self.stopped {
+            // STOP ends when one of the selected joypad lines goes low, the
+            // system clock is stopped meanwhile
+            if mem.joypad_lines() == 0x0f {
+                return Ok(4);
+            }
+            self.stopped = false;
+        }
+
+        let woke = self.halt;
+        if self.halt {
+            // HALT only ends once an enabled interrupt is requested, whether or not IME is set
+            if mem.pending_interrupts() == 0 {
+                return Ok(self.finish(mem, 4));
+            }
+            self.halt = false;
+        }
+
+        if self.interrupts.ime {
+            if let Some(interrupt) = Interrupt::highest_priority(mem.pending_interrupts()) {
+                // two wait states, push PC and jump, plus one more when leaving HALT
+                let cycles = if woke { 24 } else { 20 };
+                self.interrupts.disable();
+                mem.acknowledge_interrupt(interrupt);
+                if self.halt_bug {
+                    // EI; HALT with an interrupt pending, the interrupt returns to the HALT
+                    self.halt_bug = false;
+                    self.pc -= 1;
+                }
+                self.push_stack(mem, self.pc);
+                self.pc = interrupt.vector();
+                return Ok(self.finish(mem, cycles));
+            }
+        }
+
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.trace(self, mem);
+            self.tracer = Some(tracer);
+        }
+
         let op = self.fetch8(mem);
-        // eprintln!("Found 0x{:02X} at PC=0x{:04x}", op, self.pc - 1);
 
         // DEBUG
         if self.pc > 0x8000 && self.pc < 0xff00 {
@@ -193,70 +303,17 @@ impl CPU {
         } else {
             cycles = self.execute(mem, op);
         }
+        self.interrupts.step();
+        let cycles = self.finish(mem, cycles);
 
-        if self.interrupts {
-            let interrupt_flag = &mem.data[0xff0f];
-            let interrupt_enable = self.interrupt_reg(&mem);
-            if interrupt_enable & Interrupt::VBlank as u8 & *interrupt_flag != 0 {
-                self.interrupts = false;
-                self.push_stack(mem, self.pc);
-                self.pc = 0x0040;
-                self.unset_interrupt(mem, Interrupt::VBlank);
-            } else if interrupt_enable & Interrupt::LCDC as u8 & *interrupt_flag != 0 {
-                self.interrupts = false;
-                self.push_stack(mem, self.pc);
-                self.pc = 0x0048;
-                self.unset_interrupt(mem, Interrupt::LCDC);
-            } else if interrupt_enable & Interrupt::Timer as u8 & *interrupt_flag != 0 {
-                self.interrupts = false;
-                self.push_stack(mem, self.pc);
-                self.pc = 0x0050;
-                self.unset_interrupt(mem, Interrupt::Timer);
-            } else if interrupt_enable & Interrupt::Serial as u8 & *interrupt_flag != 0 {
-                self.interrupts = false;
-                self.push_stack(mem, self.pc);
-                self.pc = 0x0058;
-                self.unset_interrupt(mem, Interrupt::Serial);
-            } else if interrupt_enable & Interrupt::JoyPad as u8 & *interrupt_flag != 0 {
-                self.interrupts = false;
-                self.push_stack(mem, self.pc);
-                self.pc = 0x0060;
-                self.unset_interrupt(mem, Interrupt::JoyPad);
-            }
+        if self.locked {
+            return Err(Fault::IllegalOpcode { opcode: op, address: self.pc.wrapping_sub(1) });
         }
-
-        /* update timer */
-        self.update_timer(mem, cycles);
-        cycles
+        Ok(cycles)
     }
 
-    fn update_timer(&mut self, mem: &mut Memory, cycles: u16) {
-        let tac = *mem.reg_tac();
-
-        let mode: usize = tac as usize & 0b00000011;
-        let timer_on: u8 = tac & 0b00000010;
-
-        if timer_on != 0 {
-            self.timer_cycles += cycles;
-            if self.timer_cycles > TAC_SELECT[mode] {
-                self.timer_cycles -= TAC_SELECT[mode];
-                *mem.reg_tima() += 1;
-
-                if *mem.reg_tima() == 0 {
-                    *mem.reg_tima() = *mem.reg_tma();
-                    self.set_interrupt(mem, Interrupt::Timer);
-                }
-            }
-        }
-
-        self.divider_cycles += cycles;
-        if self.divider_cycles > 255 {
-            self.divider_cycles -= 255;
-            *mem.reg_div() = mem.reg_div().wrapping_add(1);
-        }
-    }
-
-    fn execute(&mut self, mem: &mut Memory, op: u8) -> u16 {
+    fn execute<B: Bus>(&mut self, mem: &mut B, op: u8) -> u16 {
+        let mut taken = false;
         let byte: u8;
         let immediate: u16;
         let result4: u8;
@@ -264,17 +321,15 @@ impl CPU {
         let addr: u16;
 
         match op {
-            0x00 => 0,
+            0x00 => {}
             0x01 => {
                 // ld bc, nn
                 immediate = self.fetch16(mem);
                 self.set_bc(immediate);
-                12
             }
             0x02 => {
                 // ld (bc), a
-                mem.write8(self.bc(), self.reg.a);
-                8
+                self.write8(mem, self.bc(), self.reg.a);
             }
             0x03 => {
                 // inc bc
@@ -282,30 +337,28 @@ impl CPU {
                 if self.reg.c == 0x00 {
                     self.reg.b = self.reg.b.wrapping_add(1);
                 }
-                8
             }
             0x04 => {
                 // inc b
-                inc8(&mut self.reg.b, &mut self.reg.f, false)
+                inc8(&mut self.reg.b, &mut self.reg.f)
             }
             0x05 => {
                 // dec b
-                dec8(&mut self.reg.b, &mut self.reg.f, false)
+                dec8(&mut self.reg.b, &mut self.reg.f)
             }
             0x06 => {
                 // ld b, n
                 self.reg.b = self.fetch8(mem);
-                8
             }
             0x07 => {
-                // rlca
-                rlc(&mut self.reg.a, &mut self.reg.f, false)
+                // rlca, unlike the CB versions Z is always cleared
+                rlc(&mut self.reg.a, &mut self.reg.f);
+                self.set_flag(FLAG_Z, false);
             }
             0x08 => {
                 // ld (nn), sp
                 addr = self.fetch16(mem);
-                mem.write16(addr, self.sp);
-                20
+                self.write16(mem, addr, self.sp);
             }
             0x09 => {
                 // add hl, bc
@@ -316,12 +369,10 @@ impl CPU {
                 self.set_flag(FLAG_H, result12 > 0xfff);
                 self.set_flag(FLAG_C, overflow);
                 self.set_hl(result);
-                8
             }
             0x0a => {
                 // ld a, (bc)
-                self.reg.a = mem.read8(self.bc());
-                8
+                self.reg.a = self.read8(mem, self.bc());
             }
             0x0b => {
                 // dec bc
@@ -329,44 +380,39 @@ impl CPU {
                 if self.reg.c == 0xff {
                     self.reg.b = self.reg.b.wrapping_sub(1);
                 }
-                8
             }
             0x0c => {
                 // inc c
-                inc8(&mut self.reg.c, &mut self.reg.f, false)
+                inc8(&mut self.reg.c, &mut self.reg.f)
             }
             0x0d => {
                 // dec c
-                dec8(&mut self.reg.c, &mut self.reg.f, false)
+                dec8(&mut self.reg.c, &mut self.reg.f)
             }
             0x0e => {
                 // ld c, n
                 self.reg.c = self.fetch8(mem);
-                8
             }
             0x0f => {
-                // rrca
-                rrc(&mut self.reg.a, &mut self.reg.f, false);
-                4
+                // rrca, unlike the CB versions Z is always cleared
+                rrc(&mut self.reg.a, &mut self.reg.f);
+                self.set_flag(FLAG_Z, false);
             }
             0x10 => {
                 // stop
-                // TODO implement correctly
-                panic!("STOP");
-                // self.pc -= 1;
-                //self.halt = 1;
-                // 4
+                self.fetch8(mem);
+                if !mem.stop() {
+                    self.stopped = true;
+                }
             }
             0x11 => {
                 // ld de, nn
                 let val = self.fetch16(mem);
                 self.set_de(val);
-                12
             }
             0x12 => {
                 // ld (de), a
-                mem.write8(self.de(), self.reg.a);
-                8
+                self.write8(mem, self.de(), self.reg.a);
             }
             0x13 => {
                 // inc de
@@ -374,30 +420,28 @@ impl CPU {
                 if self.reg.e == 0x00 {
                     self.reg.d = self.reg.d.wrapping_add(1);
                 }
-                8
             }
             0x14 => {
                 // inc d
-                inc8(&mut self.reg.d, &mut self.reg.f, false)
+                inc8(&mut self.reg.d, &mut self.reg.f)
             }
             0x15 => {
                 // dec d
-                dec8(&mut self.reg.d, &mut self.reg.f, false)
+                dec8(&mut self.reg.d, &mut self.reg.f)
             }
             0x16 => {
                 // ld d, n
                 self.reg.d = self.fetch8(mem);
-                8
             }
             0x17 => {
-                // rla
-                rl(&mut self.reg.a, &mut self.reg.f, false)
+                // rla, unlike the CB versions Z is always cleared
+                rl(&mut self.reg.a, &mut self.reg.f);
+                self.set_flag(FLAG_Z, false);
             }
             0x18 => {
                 // jr n
                 let offset = self.fetch8(mem) as i8; // signed value
                 self.pc = self.pc.wrapping_add(offset as u16);
-                8
             }
             0x19 => {
                 // add hl, de
@@ -407,12 +451,10 @@ impl CPU {
                 self.set_flag(FLAG_H, result12 > 0xfff);
                 self.set_flag(FLAG_C, carry);
                 self.set_hl(result);
-                8
             }
             0x1a => {
                 // ld a, (de)
-                let de = self.de();
-                ld_rm(&mut self.reg.a, de, &mem)
+                self.reg.a = self.read8(mem, self.de());
             }
             0x1b => {
                 // dec de
@@ -420,48 +462,44 @@ impl CPU {
                 if self.reg.e == 0xff {
                     self.reg.d = self.reg.d.wrapping_sub(1);
                 }
-                8
             }
             0x1c => {
                 // inc e
-                inc8(&mut self.reg.e, &mut self.reg.f, false)
+                inc8(&mut self.reg.e, &mut self.reg.f)
             }
             0x1d => {
                 // dec e
-                dec8(&mut self.reg.e, &mut self.reg.f, false)
+                dec8(&mut self.reg.e, &mut self.reg.f)
             }
             0x1e => {
                 // ld e, n
                 self.reg.e = self.fetch8(mem);
-                8
             }
             0x1f => {
-                // rra
-                rr(&mut self.reg.a, &mut self.reg.f, false);
-                4
+                // rra, unlike the CB versions Z is always cleared
+                rr(&mut self.reg.a, &mut self.reg.f);
+                self.set_flag(FLAG_Z, false);
             }
             0x20 => {
                 // jr nz, n
                 let offset = self.fetch8(mem) as i8; // signed value
                 if self.nz() {
+                    taken = true;
                     self.pc = self.pc.wrapping_add(offset as u16);
                 }
-                8
             }
             0x21 => {
                 // ld hl, nn
                 let val = self.fetch16(mem);
                 self.set_hl(val);
-                12
             }
             0x22 => {
                 // ldi (hl), a
-                mem.write8(self.hl(), self.reg.a);
+                self.write8(mem, self.hl(), self.reg.a);
                 self.reg.l = self.reg.l.wrapping_add(1);
                 if self.reg.l == 0x00 {
                     self.reg.h = self.reg.h.wrapping_add(1);
                 }
-                8
             }
             0x23 => {
                 // inc hl
@@ -469,20 +507,18 @@ impl CPU {
                 if self.reg.l == 0x00 {
                     self.reg.h = self.reg.h.wrapping_add(1);
                 }
-                8
             }
             0x24 => {
                 // inc h
-                inc8(&mut self.reg.h, &mut self.reg.f, false)
+                inc8(&mut self.reg.h, &mut self.reg.f)
             }
             0x25 => {
                 // dec h
-                dec8(&mut self.reg.h, &mut self.reg.f, false)
+                dec8(&mut self.reg.h, &mut self.reg.f)
             }
             0x26 => {
                 // ld h, n
                 self.reg.h = self.fetch8(mem);
-                8
             }
             0x27 => {
                 // daa
@@ -492,9 +528,9 @@ impl CPU {
                 // jr z, n
                 let offset = self.fetch8(mem) as i8; // signed value
                 if self.z() {
+                    taken = true;
                     self.pc = self.pc.wrapping_add(offset as u16);
                 }
-                8
             }
             0x29 => {
                 // add hl, hl
@@ -504,16 +540,14 @@ impl CPU {
                 self.set_flag(FLAG_H, result12 > 0xfff);
                 self.set_flag(FLAG_C, carry);
                 self.set_hl(result);
-                8
             }
             0x2a => {
                 // ldi a, (hl)
-                self.reg.a = mem.read8(self.hl());
+                self.reg.a = self.read8(mem, self.hl());
                 self.reg.l = self.reg.l.wrapping_add(1);
                 if self.reg.l == 0x00 {
                     self.reg.h = self.reg.h.wrapping_add(1);
                 }
-                8
             }
             0x2b => {
                 // dec hl
@@ -521,95 +555,83 @@ impl CPU {
                 if self.reg.l == 0xff {
                     self.reg.h = self.reg.h.wrapping_sub(1);
                 }
-                8
             }
             0x2c => {
                 // inc l
-                inc8(&mut self.reg.l, &mut self.reg.f, false)
+                inc8(&mut self.reg.l, &mut self.reg.f)
             }
             0x2d => {
                 // dec l
-                dec8(&mut self.reg.l, &mut self.reg.f, false)
+                dec8(&mut self.reg.l, &mut self.reg.f)
             }
             0x2e => {
                 // ld l, n
                 byte = self.fetch8(mem);
                 self.reg.l = byte;
-                8
             }
             0x2f => {
                 // cpl a
                 self.reg.a = !self.reg.a;
                 self.set_flag(FLAG_N, true);
                 self.set_flag(FLAG_H, true);
-                4
             }
             0x30 => {
                 // jr nc, n
                 let offset = self.fetch8(mem) as i8; // signed value
                 if self.nc() {
+                    taken = true;
                     self.pc = self.pc.wrapping_add(offset as u16);
                 }
-                8
             }
             0x31 => {
                 // ld sp, nn
                 self.sp = self.fetch16(mem);
-                12
             }
             0x32 => {
                 // ldd (hl), a
-                mem.write8(self.hl(), self.reg.a);
+                self.write8(mem, self.hl(), self.reg.a);
                 self.reg.l = self.reg.l.wrapping_sub(1);
                 if self.reg.l == 0xff {
                     self.reg.h = self.reg.h.wrapping_sub(1);
                 }
-                8
             }
             0x33 => {
                 // inc sp
                 self.sp = self.sp.wrapping_add(1);
-                8
             }
             0x34 => {
                 // inc (hl)
-                let (result, overflow) = mem.read8(self.hl()).overflowing_add(1);
-                mem.write8(self.hl(), result & 0xff);
-                self.set_flag(FLAG_Z, result == 0);
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, overflow);
-                12
+                let mut value = self.read8(mem, self.hl());
+                inc8(&mut value, &mut self.reg.f);
+                self.write8(mem, self.hl(), value);
             }
             0x35 => {
                 // dec (hl)
-                byte = mem.read8(self.hl());
+                byte = self.read8(mem, self.hl());
                 result = byte.wrapping_sub(1);
-                mem.write8(self.hl(), result);
+                self.write8(mem, self.hl(), result);
                 self.set_flag(FLAG_Z, result == 0);
                 self.set_flag(FLAG_N, true);
-                self.set_flag(FLAG_H, byte & 0xf != 0);
-                12
+                self.set_flag(FLAG_H, byte & 0xf == 0);
             }
             0x36 => {
                 // ld (hl), n
                 byte = self.fetch8(mem);
-                mem.write8(self.hl(), byte);
-                12
+                self.write8(mem, self.hl(), byte);
             }
             0x37 => {
                 // scf
                 self.set_flag(FLAG_N, false);
                 self.set_flag(FLAG_H, false);
                 self.set_flag(FLAG_C, true);
-                4
             }
             0x38 => {
                 // jr c, n
                 let offset = self.fetch8(mem) as i8; // signed value
                 if self.c() {
+                    taken = true;
                     self.pc = self.pc.wrapping_add(offset as u16);
                 }
-                8
             }
             0x39 => {
                 // add hl, sp
@@ -619,389 +641,325 @@ impl CPU {
                 self.set_flag(FLAG_H, result12 > 0xfff);
                 self.set_flag(FLAG_C, carry);
                 self.set_hl(result);
-                8
             }
             0x3a => {
                 // ldd a, (hl)
-                self.reg.a = mem.read8(self.hl());
+                self.reg.a = self.read8(mem, self.hl());
                 self.reg.l = self.reg.l.wrapping_sub(1);
                 if self.reg.l == 0xff {
                     self.reg.h = self.reg.h.wrapping_sub(1);
                 }
-                8
             }
             0x3b => {
                 // dec sp
                 self.sp = self.sp.wrapping_sub(1);
-                8
             }
             0x3c => {
                 // inc a
-                inc8(&mut self.reg.a, &mut self.reg.f, false)
+                inc8(&mut self.reg.a, &mut self.reg.f)
             }
             0x3d => {
                 // dec a
-                dec8(&mut self.reg.a, &mut self.reg.f, false)
+                dec8(&mut self.reg.a, &mut self.reg.f)
             }
             0x3e => {
                 // ld a, n
                 self.reg.a = self.fetch8(mem);
-                16
             }
             0x3f => {
                 // ccf
                 self.set_flag(FLAG_N, false);
                 self.set_flag(FLAG_H, false);
                 self.set_flag(FLAG_C, !self.c());
-                4
             }
             0x40 => {
                 // ld b, b
                 self.reg.b = self.reg.b;
-                4
             }
             0x41 => {
                 // ld b, c
                 self.reg.b = self.reg.c;
-                4
             }
             0x42 => {
                 // ld b, d
                 self.reg.b = self.reg.d;
-                4
             }
             0x43 => {
                 // ld b, e
                 self.reg.b = self.reg.e;
-                4
             }
             0x44 => {
                 // ld b, h
                 self.reg.b = self.reg.h;
-                4
             }
             0x45 => {
                 // ld b, l
                 self.reg.b = self.reg.l;
-                4
             }
             0x46 => {
                 // ld b, (hl)
-                self.reg.b = mem.read8(self.hl());
-                8
+                self.reg.b = self.read8(mem, self.hl());
             }
             0x47 => {
                 // ld b, a
                 self.reg.b = self.reg.a;
-                4
             }
             0x48 => {
                 // ld c, b
                 self.reg.c = self.reg.b;
-                4
             }
             0x49 => {
                 // ld c, c
                 self.reg.c = self.reg.c;
-                4
             }
             0x4a => {
                 // ld c, d
                 self.reg.c = self.reg.d;
-                4
             }
             0x4b => {
                 // ld c, e
                 ld_rr(&mut self.reg.c, &self.reg.e);
-                4
             }
             0x4c => {
                 // ld c, h
                 ld_rr(&mut self.reg.c, &self.reg.h);
-                4
             }
             0x4d => {
                 // ld c, l
                 ld_rr(&mut self.reg.c, &self.reg.l);
-                4
             }
             0x4e => {
                 // ld c, (hl)
-                self.reg.c = mem.read8(self.hl());
-                8
+                self.reg.c = self.read8(mem, self.hl());
             }
             0x4f => {
                 // ld c, a
                 self.reg.c = self.reg.a;
-                4
             }
             0x50 => {
                 // ld d, b
                 self.reg.d = self.reg.b;
-                4
             }
             0x51 => {
                 // ld d, c
                 self.reg.d = self.reg.c;
-                4
             }
             0x52 => {
                 // ld d, d
                 self.reg.d = self.reg.d;
-                4
             }
             0x53 => {
                 // ld d, e
                 self.reg.d = self.reg.e;
-                4
             }
             0x54 => {
                 // ld d, h
                 self.reg.d = self.reg.h;
-                4
             }
             0x55 => {
                 // ld d, l
                 self.reg.d = self.reg.l;
-                4
             }
             0x56 => {
                 // ld d, (hl)
-                self.reg.d = mem.read8(self.hl());
-                8
+                self.reg.d = self.read8(mem, self.hl());
             }
             0x57 => {
                 // ld d, a
                 self.reg.d = self.reg.a;
-                4
             }
             0x58 => {
                 // ld e, b
                 self.reg.e = self.reg.b;
-                4
             }
             0x59 => {
                 // ld e, c
                 self.reg.e = self.reg.c;
-                4
             }
             0x5a => {
                 // ld e, d
                 self.reg.e = self.reg.d;
-                4
             }
             0x5b => {
                 // ld e, e
                 self.reg.e = self.reg.e;
-                4
             }
             0x5c => {
                 // ld e, h
                 self.reg.e = self.reg.h;
-                4
             }
             0x5d => {
                 // ld e, l
                 self.reg.e = self.reg.l;
-                4
             }
             0x5e => {
                 // ld e, (hl)
-                self.reg.e = mem.read8(self.hl());
-                8
+                self.reg.e = self.read8(mem, self.hl());
             }
             0x5f => {
                 // ld e, a
                 self.reg.e = self.reg.a;
-                4
             }
             0x60 => {
                 // ld h, b
                 self.reg.h = self.reg.b;
-                4
             }
             0x61 => {
                 // ld h, c
                 self.reg.h = self.reg.c;
-                4
             }
             0x62 => {
                 // ld h, d
                 self.reg.h = self.reg.d;
-                4
             }
             0x63 => {
                 // ld h, e
                 self.reg.h = self.reg.e;
-                4
             }
             0x64 => {
                 // ld h, h
                 self.reg.h = self.reg.h;
-                4
             }
             0x65 => {
                 // ld h, l
                 self.reg.h = self.reg.l;
-                4
             }
             0x66 => {
                 // ld h, (hl)
-                self.reg.h = mem.read8(self.hl());
-                8
+                self.reg.h = self.read8(mem, self.hl());
             }
             0x67 => {
                 // ld h, a
                 self.reg.h = self.reg.a;
-                4
             }
             0x68 => {
                 // ld l, b
                 self.reg.l = self.reg.b;
-                4
             }
             0x69 => {
                 // ld l, c
                 self.reg.l = self.reg.c;
-                4
             }
             0x6a => {
                 // ld l, d
                 self.reg.l = self.reg.d;
-                4
             }
             0x6b => {
                 // ld l, e
                 self.reg.l = self.reg.e;
-                4
             }
             0x6c => {
                 // ld l, h
                 self.reg.l = self.reg.h;
-                4
             }
             0x6d => {
                 // ld l, l
                 self.reg.l = self.reg.l;
-                4
             }
             0x6e => {
                 // ld l, (hl)
-                self.reg.l = mem.read8(self.hl());
-                8
+                self.reg.l = self.read8(mem, self.hl());
             }
             0x6f => {
                 // ld l, a
                 self.reg.l = self.reg.a;
-                4
             }
             0x70 => {
                 // ld (hl), b
-                mem.write8(self.hl(), self.reg.b);
-                8
+                self.write8(mem, self.hl(), self.reg.b);
             }
             0x71 => {
                 // ld (hl), c
-                mem.write8(self.hl(), self.reg.c);
-                8
+                self.write8(mem, self.hl(), self.reg.c);
             }
             0x72 => {
                 // ld (hl), d
-                mem.write8(self.hl(), self.reg.d);
-                8
+                self.write8(mem, self.hl(), self.reg.d);
             }
             0x73 => {
                 // ld (hl), e
-                mem.write8(self.hl(), self.reg.e);
-                8
+                self.write8(mem, self.hl(), self.reg.e);
             }
             0x74 => {
                 // ld (hl), h
-                mem.write8(self.hl(), self.reg.h);
-                8
+                self.write8(mem, self.hl(), self.reg.h);
             }
             0x75 => {
                 // ld (hl), l
-                mem.write8(self.hl(), self.reg.l);
-                8
+                self.write8(mem, self.hl(), self.reg.l);
             }
             0x76 => {
                 // halt
-                // panic!("HALT");
-                4
+                if !self.interrupts.ime && mem.pending_interrupts() != 0 {
+                    // HALT bug: the CPU doesn't halt and fails to increment PC on the next fetch
+                    self.halt_bug = true;
+                } else {
+                    self.halt = true;
+                }
             }
             0x77 => {
                 // ld (hl), a
-                mem.write8(self.hl(), self.reg.a);
-                8
+                self.write8(mem, self.hl(), self.reg.a);
             }
             0x78 => {
                 // ld a, b
                 self.reg.a = self.reg.b;
-                4
             }
             0x79 => {
                 // ld a, c
                 self.reg.a = self.reg.c;
-                4
             }
             0x7a => {
                 // ld a, d
                 self.reg.a = self.reg.d;
-                4
             }
             0x7b => {
                 // ld a, e
                 self.reg.a = self.reg.e;
-                4
             }
             0x7c => {
                 // ld a, h
                 self.reg.a = self.reg.h;
-                4
             }
             0x7d => {
                 // ld a, l
                 self.reg.a = self.reg.l;
-                4
             }
             0x7e => {
                 // ld a, (hl)
-                self.reg.a = mem.read8(self.hl());
-                8
+                self.reg.a = self.read8(mem, self.hl());
             }
             0x7f => {
                 // ld a, a
                 self.reg.a = self.reg.a;
-                4
             }
             0x80 => {
                 // add a, b
-                add(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0x81 => {
                 // add a, c
-                add(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0x82 => {
                 // add a, d
-                add(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0x83 => {
                 // add a, e
-                add(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0x84 => {
                 // add a, h
-                add(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0x85 => {
                 // add a, l
-                add(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                add(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0x86 => {
                 // add a, (hl)
-                byte = mem.read8(self.hl());
+                byte = self.read8(mem, self.hl());
                 let (result, overflow) = self.reg.a.overflowing_add(byte);
                 result4 = (self.reg.a & 0xf) + (byte & 0xf);
                 self.set_flag(FLAG_Z, result == 0);
@@ -1009,469 +967,435 @@ impl CPU {
                 self.set_flag(FLAG_H, result4 > 0xf);
                 self.set_flag(FLAG_C, overflow);
                 self.reg.a = result;
-                8
             }
             0x87 => {
                 // add a, a
                 let a = self.reg.a;
-                add(&mut self.reg.a, a, &mut self.reg.f, false)
+                add(&mut self.reg.a, a, &mut self.reg.f)
             }
             0x88 => {
                 // adc a, b
-                adc(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0x89 => {
                 // adc a, c
-                adc(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0x8a => {
-                adc(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0x8b => {
-                adc(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0x8c => {
-                adc(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0x8d => {
-                adc(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                adc(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0x8e => {
                 // adc a, (hl)
-                byte = mem.read8(self.hl());
-                adc(&mut self.reg.a, byte, &mut self.reg.f, false)
+                byte = self.read8(mem, self.hl());
+                adc(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0x8f => {
                 let a = self.reg.a;
-                adc(&mut self.reg.a, a, &mut self.reg.f, false)
+                adc(&mut self.reg.a, a, &mut self.reg.f)
             }
             0x90 => {
                 // sub a, b
-                sub(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0x91 => {
                 // sub a, c
-                sub(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0x92 => {
                 // sub a, d
-                sub(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0x93 => {
                 // sub a, e
-                sub(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0x94 => {
                 // sub a, h
-                sub(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0x95 => {
                 // sub a, l
-                sub(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                sub(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0x96 => {
                 // sub a, (hl)
-                byte = mem.read8(self.hl());
-                sub(&mut self.reg.a, byte, &mut self.reg.f, true)
+                byte = self.read8(mem, self.hl());
+                sub(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0x97 => {
                 // sub a, a
                 let a = self.reg.a;
-                sub(&mut self.reg.a, a, &mut self.reg.f, false)
+                sub(&mut self.reg.a, a, &mut self.reg.f)
             }
             0x98 => {
                 // sbc a, b
-                sbc(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0x99 => {
                 // sbc a, c
-                sbc(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0x9a => {
                 // sbc a, d
-                sbc(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0x9b => {
                 // sbc a, e
-                sbc(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0x9c => {
                 // sbc a, h
-                sbc(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0x9d => {
                 // sbc a, l
-                sbc(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0x9e => {
                 // sbc a, (hl)
-                byte = mem.read8(self.hl());
-                sbc(&mut self.reg.a, byte, &mut self.reg.f, false)
+                byte = self.read8(mem, self.hl());
+                sbc(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0x9f => {
                 // sbc a, a
                 let a = self.reg.a;
-                sbc(&mut self.reg.a, a, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, a, &mut self.reg.f)
             }
             0xa0 => {
                 // and a, b
-                and(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0xa1 => {
                 // and a, c
-                and(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0xa2 => {
                 // and a, d
-                and(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0xa3 => {
                 // and a, e
-                and(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0xa4 => {
                 // and a, h
-                and(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0xa5 => {
                 // and a, l
-                and(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                and(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0xa6 => {
                 // and a, (hl)
-                let indirect = mem.read8(self.hl());
-                and(&mut self.reg.a, indirect, &mut self.reg.f, false)
+                let indirect = self.read8(mem, self.hl());
+                and(&mut self.reg.a, indirect, &mut self.reg.f)
             }
             0xa7 => {
                 // and a, a
                 let a = self.reg.a;
-                and(&mut self.reg.a, a, &mut self.reg.f, false);
-                4
+                and(&mut self.reg.a, a, &mut self.reg.f);
             }
             0xa8 => {
                 // xor a, b
-                xor(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0xa9 => {
                 // xor a, c
-                xor(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0xaa => {
                 // xor a, d
-                xor(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0xab => {
                 // xor a, e
-                xor(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0xac => {
                 // xor a, h
-                xor(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0xad => {
                 // xor a, l
-                xor(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                xor(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0xae => {
                 // xor a, (hl)
-                let hl = mem.data[self.hl() as usize];
-                xor(&mut self.reg.a, hl, &mut self.reg.f, true)
+                let hl = self.read8(mem, self.hl());
+                xor(&mut self.reg.a, hl, &mut self.reg.f)
             }
             0xaf => {
                 // xor a, a
                 let a = self.reg.a;
-                xor(&mut self.reg.a, a, &mut self.reg.f, false)
+                xor(&mut self.reg.a, a, &mut self.reg.f)
             }
             0xb0 => {
                 // or b
-                or(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0xb1 => {
                 // or c
-                or(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0xb2 => {
                 // or d
-                or(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0xb3 => {
                 // or e
-                or(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0xb4 => {
                 // or h
-                or(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0xb5 => {
                 // or l
-                or(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                or(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0xb6 => {
                 // or (hl)
-                byte = mem.data[self.hl() as usize];
-                or(&mut self.reg.a, byte, &mut self.reg.f, true)
+                byte = self.read8(mem, self.hl());
+                or(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xb7 => {
                 // or a
                 let a = self.reg.a;
-                or(&mut self.reg.a, a, &mut self.reg.f, false)
+                or(&mut self.reg.a, a, &mut self.reg.f)
             }
             0xb8 => {
                 // cp a, b
-                cp(&mut self.reg.a, self.reg.b, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.b, &mut self.reg.f)
             }
             0xb9 => {
                 // cp a, c
-                cp(&mut self.reg.a, self.reg.c, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.c, &mut self.reg.f)
             }
             0xba => {
                 // cp a, d
-                cp(&mut self.reg.a, self.reg.d, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.d, &mut self.reg.f)
             }
             0xbb => {
                 // cp a, e
-                cp(&mut self.reg.a, self.reg.e, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.e, &mut self.reg.f)
             }
             0xbc => {
                 // cp a, h
-                cp(&mut self.reg.a, self.reg.h, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.h, &mut self.reg.f)
             }
             0xbd => {
                 // cp a, l
-                cp(&mut self.reg.a, self.reg.l, &mut self.reg.f, false)
+                cp(&mut self.reg.a, self.reg.l, &mut self.reg.f)
             }
             0xbe => {
                 // cp a, (hl)
-                byte = mem.read8(self.hl());
-                cp(&mut self.reg.a, byte, &mut self.reg.f, true)
+                byte = self.read8(mem, self.hl());
+                cp(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xbf => {
                 // cp a, a
                 let a = self.reg.a;
-                cp(&mut self.reg.a, a, &mut self.reg.f, false)
+                cp(&mut self.reg.a, a, &mut self.reg.f)
             }
             0xc0 => {
                 // ret nz
                 if self.nz() {
+                    taken = true;
                     self.pc = self.pop_stack(mem);
                 }
-                8
             }
             0xc1 => {
                 // pop bc
                 let val = self.pop_stack(mem);
                 self.set_bc(val);
-                12
             }
             0xc2 => {
                 // jp nz, nn
                 addr = self.fetch16(mem);
                 if self.nz() {
+                    taken = true;
                     self.pc = addr;
                 }
-                12
             }
             0xc3 => {
                 // jp nn
                 self.pc = self.fetch16(mem);
-                12
             }
             0xc4 => {
                 // call nz, nn
                 addr = self.fetch16(mem);
                 if self.nz() {
+                    taken = true;
                     self.push_stack(mem, self.pc);
                     self.pc = addr;
                 }
-                12
             }
             0xc5 => {
                 // push bc
                 self.push_stack(mem, self.bc());
-                16
             }
             0xc6 => {
                 // add a, #
                 byte = self.fetch8(mem);
-                add(&mut self.reg.a, byte, &mut self.reg.f, true)
+                add(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xc7 => {
                 // rst $00
                 self.push_stack(mem, self.pc);
                 self.pc = 0x0;
-                32
             }
             0xc8 => {
                 // ret z
                 if self.z() {
+                    taken = true;
                     self.pc = self.pop_stack(mem);
                 }
-                8
             }
             0xc9 => {
                 // ret
                 self.pc = self.pop_stack(mem);
-                8
             }
             0xca => {
                 // jp z, nn
                 addr = self.fetch16(mem);
                 if self.z() {
+                    taken = true;
                     self.pc = addr;
                 }
-                12
             }
             0xcc => {
                 // call z, nn
                 let addr = self.fetch16(mem);
                 if self.z() {
+                    taken = true;
                     self.push_stack(mem, self.pc);
                     self.pc = addr;
                 }
-                12
             }
             0xcd => {
                 // call nn
                 addr = self.fetch16(mem);
                 self.push_stack(mem, self.pc);
                 self.pc = addr;
-                12
-
             }
             0xce => {
                 // adc a, #
                 byte = self.fetch8(mem);
-                adc(&mut self.reg.a, byte, &mut self.reg.f, false)
+                adc(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xcf => {
                 // rst $8
                 self.push_stack(mem, self.pc);
                 self.pc = 0x8;
-                32
             }
             0xd0 => {
                 // ret nc
                 if self.nc() {
+                    taken = true;
                     self.pc = self.pop_stack(mem);
                 }
-                8
             }
             0xd1 => {
                 // pop de
                 let val = self.pop_stack(mem);
                 self.set_de(val);
-                12
             }
             0xd2 => {
                 // jp nc, nn
                 addr = self.fetch16(mem);
                 if self.nc() {
+                    taken = true;
                     self.pc = addr;
                 }
-                12
-            }
-            0xd3 => {
-                panic!("Invalid instruction: 0x{:02X}", op);
             }
             0xd4 => {
                 // call nc, nn
                 addr = self.fetch16(mem);
                 if self.nc() {
+                    taken = true;
                     self.push_stack(mem, self.pc);
                     self.pc = addr;
                 }
-                12
             }
             0xd5 => {
                 // push de
                 self.push_stack(mem, self.de());
-                16
             }
             0xd6 => {
                 // sub a, #
                 byte = self.fetch8(mem);
-                sub(&mut self.reg.a, byte, &mut self.reg.f, false)
+                sub(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xd7 => {
                 // rst $10
                 self.push_stack(mem, self.pc);
                 self.pc = 0x10;
-                32
             }
             0xd8 => {
                 // ret c
                 if self.c() {
+                    taken = true;
                     self.pc = self.pop_stack(mem);
                 }
-                8
             }
             0xd9 => {
                 // reti
                 self.pc = self.pop_stack(mem);
-                self.interrupts = true;
-                8
+                self.interrupts.enable();
             }
             0xda => {
                 // jp c, nn
                 addr = self.fetch16(mem);
                 if self.c() {
+                    taken = true;
                     self.pc = addr;
                 }
-                12
-            }
-            0xdb => {
-                panic!("Invalid instruction: 0x{:02X}", op);
             }
             0xdc => {
                 // call c, nn
                 addr = self.fetch16(mem);
                 if self.c() {
+                    taken = true;
                     self.push_stack(mem, self.pc);
                     self.pc = addr;
                 }
-                12
-            }
-            0xdd => {
-                panic!("Invalid instruction: 0x{:02X}", op);
             }
             0xde => {
                 // sbc a, #
                 byte = self.fetch8(mem);
-                sbc(&mut self.reg.a, byte, &mut self.reg.f, false)
+                sbc(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xdf => {
                 // rst $18
                 self.push_stack(mem, self.pc);
                 self.pc = 0x18;
-                32
             }
             0xe0 => {
                 // ld ($ff00+n), a
                 let n = self.fetch8(mem) as u16;
-                mem.write8(0xff00 + n, self.reg.a);
-                12
+                self.write8(mem, 0xff00 + n, self.reg.a);
             }
             0xe1 => {
                 // pop hl
                 let val = self.pop_stack(mem);
                 self.set_hl(val);
-                12
             }
             0xe2 => {
                 // ld ($ff00+c), a
-                mem.write8(0xff00 + self.reg.c as u16, self.reg.a);
-                8
-            }
-            0xe3 => {
-                panic!("Invalid instruction: 0x{:02X}", op);
-            }
-            0xe4 => {
-                panic!("Invalid instruction: 0x{:02X}", op);
+                self.write8(mem, 0xff00 + self.reg.c as u16, self.reg.a);
             }
             0xe5 => {
                 // push hl
                 self.push_stack(mem, self.hl());
-                16
             }
             0xe6 => {
                 // and a, #
@@ -1482,90 +1406,62 @@ impl CPU {
                 self.set_flag(FLAG_H, true);
                 self.set_flag(FLAG_C, false);
                 self.reg.a = result;
-                8
             }
             0xe7 => {
                 // rst $20
                 self.push_stack(mem, self.pc);
                 self.pc = 0x20;
-                32
             }
             0xe8 => {
-                immediate = ((mem.read8(self.pc) as i8) as i16) as u16;
-                let (result, carry) = self.sp.overflowing_add(immediate);
-                let result12 = (self.sp & 0xfff) + (immediate & 0xfff);
-                self.set_flag(FLAG_Z, false);
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, result12 > 0xfff);
-                self.set_flag(FLAG_C, carry);
-                self.sp = result;
-                16
+                // add sp, n
+                byte = self.fetch8(mem);
+                self.sp = self.add_sp_offset(byte);
             }
             0xe9 => {
                 // jp hl
                 // I've seen this instruction as jp (hl) but the parenthesis seem misleading
                 // since we aren't dereferencing hl, leaving them out
                 self.pc = self.hl();
-                4
             }
             0xea => {
                 // ld (nn), a
                 addr = self.fetch16(mem);
-                mem.write8(addr, self.reg.a);
-                16
-            }
-            0xeb => {
-                panic!("invalid");
-            }
-            0xec => {
-                panic!("invalid");
-            }
-            0xed => {
-                panic!("invalid");
+                self.write8(mem, addr, self.reg.a);
             }
             0xee => {
                 // xor #
                 byte = self.fetch8(mem);
-                xor(&mut self.reg.a, byte, &mut self.reg.f, false);
-                8
+                xor(&mut self.reg.a, byte, &mut self.reg.f);
             }
             0xef => {
                 // rst $28
                 self.push_stack(mem, self.pc);
                 self.pc = 0x28;
-                32
             }
             0xf0 => {
                 // ld a, ($ff00+n)
                 let val = self.fetch8(mem) as u16;
-                self.reg.a = mem.read8(0xff00 + val);
-                12
+                self.reg.a = self.read8(mem, 0xff00 + val);
             }
             0xf1 => {
                 // pop af
                 let val = self.pop_stack(mem);
-                self.set_af(val);
-                12
+                // the low nibble of F doesn't exist
+                self.set_af(val & 0xfff0);
             }
             0xf2 => {
                 // ld a, ($ff00+c)
                 addr = (self.reg.c as u16).wrapping_add(0xff00);
-                self.reg.a = mem.read8(addr);
-                8
+                self.reg.a = self.read8(mem, addr);
             }
             0xf3 => {
                 // disable int
-                self.interrupts = false;
-                4
-            }
-            0xf4 => {
-                panic!("Invalid instruction: 0x{:02X}", op);
+                self.interrupts.disable();
             }
             0xf5 => {
                 // push af
                 let val = self.af();
                 self.push_stack(mem, val);
-                16
             }
             0xf6 => {
                 // or a, #
@@ -1575,186 +1471,180 @@ impl CPU {
                 self.set_flag(FLAG_N, false);
                 self.set_flag(FLAG_H, false);
                 self.set_flag(FLAG_C, false);
-                8
             }
             0xf7 => {
                 // rst $30
                 self.push_stack(mem, self.pc);
                 self.pc = 0x30;
-                32
             }
             0xf8 => {
                 // ld hl, sp+$n
-                // panic!("Unimplemented Instruction");
-                let val = self.fetch8(mem) as i8;
-                let result = self.sp.wrapping_add(val as u16);
-                // let result4 = (self.sp & 0xf) + (val & 0xf);
-                self.set_flag(FLAG_Z, false);
-                self.set_flag(FLAG_N, false);
-                // self.set_flag(FLAG_H, result4 > 0xf);
-                self.set_flag(FLAG_C, result > 0xff);
-
+                byte = self.fetch8(mem);
+                let result = self.add_sp_offset(byte);
                 self.set_hl(result);
-                12
             }
             0xf9 => {
                 // ld sp, hl
                 self.sp = self.hl();
-                8
             }
             0xfa => {
                 // ld a, (nn)
                 addr = self.fetch16(mem);
-                self.reg.a = mem.read8(addr);
-                16
+                self.reg.a = self.read8(mem, addr);
             }
             0xfb => {
-                // enable int
-                self.interrupts = true;
-                4
-            }
-            0xfc => {
-                panic!("Invalid instruction: 0x{:02X}", op);
-            }
-            0xfd => {
-                panic!("Invalid instruction: 0x{:02X}", op);
+                // enable int, takes effect after the next instruction
+                self.interrupts.enable_delayed();
             }
             0xfe => {
                 // cp a, n
                 byte = self.fetch8(mem);
-                cp(&mut self.reg.a, byte, &mut self.reg.f, true)
+                cp(&mut self.reg.a, byte, &mut self.reg.f)
             }
             0xff => {
                 // rst $38
                 self.push_stack(mem, self.pc);
                 self.pc = 0x38;
-                32
             }
-            _ => {
-                panic!("Unhandled instruction: 0x{:02X}", op);
+            0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+                // unused opcodes hang the CPU until reset
+                self.locked = true;
             }
+            0xcb => unreachable!("0xCB prefixed instructions go through execute_cb"),
         }
+
+        opcodes::lookup(op).cycles(taken)
     }
 
-    fn execute_cb(&mut self, mem: &mut Memory, op: u8) -> u16 {
+    fn execute_cb<B: Bus>(&mut self, mem: &mut B, op: u8) -> u16 {
         /* 0b76543210 */
         /*   xxyyyzzz */
         let x = op >> 6;
         let y = (op >> 3) & 7;
         let z = op & 7;
 
-        let reg = match z {
-            0 => &mut self.reg.b,
-            1 => &mut self.reg.c,
-            2 => &mut self.reg.d,
-            3 => &mut self.reg.e,
-            4 => &mut self.reg.h,
-            5 => &mut self.reg.l,
-            6 => &mut mem.data[self.hl() as usize],
-            7 => &mut self.reg.a,
+        // (hl) goes through the bus like any other access, read and then
+        // written back unless it's a BIT
+        let hl = self.hl();
+        let mut value = match z {
+            0 => self.reg.b,
+            1 => self.reg.c,
+            2 => self.reg.d,
+            3 => self.reg.e,
+            4 => self.reg.h,
+            5 => self.reg.l,
+            6 => self.read8(mem, hl),
+            7 => self.reg.a,
             _ => panic!("???"),
         };
-
-        let indirect = z == 6;
+        let reg = &mut value;
 
         match x {
             0 => {
                 match y {
-                    0 => rlc(reg, &mut self.reg.f, indirect),
-                    1 => rrc(reg, &mut self.reg.f, indirect),
-                    2 => rl(reg, &mut self.reg.f, indirect),
-                    3 => rr(reg, &mut self.reg.f, indirect),
-                    4 => sla(reg, &mut self.reg.f, indirect),
-                    5 => sra(reg, &mut self.reg.f, indirect),
-                    6 => swap(reg, &mut self.reg.f, indirect),
-                    7 => srl(reg, &mut self.reg.f, indirect),
+                    0 => rlc(reg, &mut self.reg.f),
+                    1 => rrc(reg, &mut self.reg.f),
+                    2 => rl(reg, &mut self.reg.f),
+                    3 => rr(reg, &mut self.reg.f),
+                    4 => sla(reg, &mut self.reg.f),
+                    5 => sra(reg, &mut self.reg.f),
+                    6 => swap(reg, &mut self.reg.f),
+                    7 => srl(reg, &mut self.reg.f),
                     _ => panic!("Unhandled instruction: 0xCB 0x{:02X}", op),
                 }
             }
-            1 => bit(*reg, y, &mut self.reg.f, indirect),
-            2 => res(reg, y, indirect),
-            3 => set(reg, y, indirect),
+            1 => {
+                bit(*reg, y, &mut self.reg.f);
+                return opcodes::lookup_cb(op).cycles(false);
+            }
+            2 => res(reg, y),
+            3 => set(reg, y),
             _ => panic!("Unhandled instruction: 0xCB 0x{:02X}", op),
         }
+
+        match z {
+            0 => self.reg.b = value,
+            1 => self.reg.c = value,
+            2 => self.reg.d = value,
+            3 => self.reg.e = value,
+            4 => self.reg.h = value,
+            5 => self.reg.l = value,
+            6 => self.write8(mem, hl, value),
+            7 => self.reg.a = value,
+            _ => panic!("???"),
+        }
+
+        opcodes::lookup_cb(op).cycles(false)
     }
 }
 
-fn add(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn add(dst: &mut u8, src: u8, flags: &mut u8) {
     let (result, carry) = dst.overflowing_add(src);
     set_flag(flags, FLAG_Z, result == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, (*dst & 0xf) + (src & 0xf) > 0xf);
     set_flag(flags, FLAG_C, carry);
     *dst = result;
-    if indirect { 8 } else { 4 }
 }
 
-fn sub(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn sub(dst: &mut u8, src: u8, flags: &mut u8) {
     let result = dst.wrapping_sub(src);
     set_flag(flags, FLAG_Z, result == 0);
     set_flag(flags, FLAG_N, true);
     set_flag(flags, FLAG_H, (*dst & 0xf) < (src & 0xf));
     set_flag(flags, FLAG_C, *dst < src);
     *dst = result;
-    if indirect { 8 } else { 4 }
 }
 
-fn cp(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn cp(dst: &mut u8, src: u8, flags: &mut u8) {
     let result = dst.wrapping_sub(src);
     set_flag(flags, FLAG_Z, result == 0);
     set_flag(flags, FLAG_N, true);
     set_flag(flags, FLAG_H, (*dst & 0xf) < (src & 0xf));
     set_flag(flags, FLAG_C, *dst < src);
-    if indirect { 8 } else { 4 }
 }
 
-fn xor(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn xor(dst: &mut u8, src: u8, flags: &mut u8) {
     *dst ^= src;
     *flags = if *dst == 0 { FLAG_Z } else { 0 };
-    if indirect { 8 } else { 4 }
 }
 
-fn or(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn or(dst: &mut u8, src: u8, flags: &mut u8) {
     *dst |= src;
     *flags = if *dst == 0 { FLAG_Z } else { 0 };
-    if indirect { 8 } else { 4 }
 }
 
-fn and(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn and(dst: &mut u8, src: u8, flags: &mut u8) {
     *dst &= src;
     *flags = if *dst == 0 { FLAG_Z | FLAG_H } else { FLAG_H };
-    if indirect { 8 } else { 4 }
 }
 
-fn adc(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn adc(dst: &mut u8, src: u8, flags: &mut u8) {
     let carry = if *flags & FLAG_C == 0 { 0 } else { 1 };
-    let (result, overflow) = dst.overflowing_add(src.wrapping_add(carry));
+    // src + carry can overflow on its own, so add everything at 16 bits
+    let result = *dst as u16 + src as u16 + carry as u16;
 
-    if result == 0 { set_flag(flags, FLAG_Z, true); }
+    set_flag(flags, FLAG_Z, result as u8 == 0);
     set_flag(flags, FLAG_N, false);
-    if (*dst & 0xf) + (src.wrapping_add(carry) & 0xf) > 0xf { set_flag(flags, FLAG_H, true); }
-    if overflow { set_flag(flags, FLAG_C, true); }
-
-    *dst = result;
+    set_flag(flags, FLAG_H, (*dst & 0xf) + (src & 0xf) + carry > 0xf);
+    set_flag(flags, FLAG_C, result > 0xff);
 
-    if indirect { 8 } else { 4 }
+    *dst = result as u8;
 }
 
-fn sbc(dst: &mut u8, src: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn sbc(dst: &mut u8, src: u8, flags: &mut u8) {
     let carry = if *flags & FLAG_C == 0 { 0 } else { 1 };
-    let (result, borrow) = dst.overflowing_sub(src.wrapping_add(carry));
+    let result = *dst as i16 - src as i16 - carry as i16;
 
-    if result == 0 { set_flag(flags, FLAG_Z, true); }
+    set_flag(flags, FLAG_Z, result as u8 == 0);
     set_flag(flags, FLAG_N, true);
-    if (*dst & 0xf) < (src.wrapping_add(carry) & 0xf) { set_flag(flags, FLAG_H, true); }
-    if !borrow { set_flag(flags, FLAG_C, true); }
+    set_flag(flags, FLAG_H, (*dst & 0xf) as i16 - (src & 0xf) as i16 - (carry as i16) < 0);
+    set_flag(flags, FLAG_C, result < 0);
 
-    *dst = result;
-
-    if indirect { 8 } else { 4 }
+    *dst = result as u8;
 }
 
-fn inc8(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn inc8(dst: &mut u8, flags: &mut u8) {
     let half_carry = (*dst & 0xf) == 0xf;
     *dst = dst.wrapping_add(1);
     if *dst == 0 {
@@ -1769,10 +1659,9 @@ fn inc8(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
     } else {
         *flags &= !FLAG_H;
     }
-    if indirect { 12 } else { 4 }
 }
 
-fn dec8(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn dec8(dst: &mut u8, flags: &mut u8) {
     let half_borrow = (*dst & 0xf) == 0x0;
     *dst = dst.wrapping_sub(1);
     if *dst == 0 {
@@ -1782,25 +1671,23 @@ fn dec8(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
     }
     *flags |= FLAG_N;
 
-    if !half_borrow {
+    if half_borrow {
         *flags |= FLAG_H;
     } else {
         *flags &= !FLAG_H;
     }
-    if indirect { 12 } else { 4 }
 }
 
-fn rlc(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn rlc(dst: &mut u8, flags: &mut u8) {
     let old_bit7 = *dst & (1 << 7);
-    *dst = *dst << 1;
+    *dst = dst.rotate_left(1);
     set_flag(flags, FLAG_Z, *dst == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, old_bit7 != 0);
-    if indirect { 16 } else { 8 }
 }
 
-fn rr(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn rr(dst: &mut u8, flags: &mut u8) {
     let bit0 = *dst & 1;
     *dst = *dst >> 1;
     if (*flags & FLAG_C) != 0 {
@@ -1811,21 +1698,18 @@ fn rr(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, bit0 != 0);
-
-    if indirect { 16 } else { 8 }
 }
 
-fn rrc(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
-    let old_bit0 = *dst & (1 << 7);
-    *dst = *dst >> 1;
+fn rrc(dst: &mut u8, flags: &mut u8) {
+    let old_bit0 = *dst & 1;
+    *dst = dst.rotate_right(1);
     set_flag(flags, FLAG_Z, *dst == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, old_bit0 != 0);
-    if indirect { 16 } else { 8 }
 }
 
-fn rl(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn rl(dst: &mut u8, flags: &mut u8) {
     let bit7 = *dst & (1 << 7);
     *dst = *dst << 1;
     if (*flags & FLAG_C) != 0 {
@@ -1836,11 +1720,9 @@ fn rl(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, bit7 != 0);
-
-    if indirect { 16 } else { 8 }
 }
 
-fn srl(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn srl(dst: &mut u8, flags: &mut u8) {
     let bit0 = *dst & 1;
     *dst = *dst >> 1;
 
@@ -1848,78 +1730,312 @@ fn srl(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, bit0 != 0);
-
-    if indirect { 16 } else { 8 }
 }
 
 
-fn daa(dst: &mut u8, flags: &mut u8) -> u16 {
-    let (high, low) = (*dst >> 4 & 0xf, *dst & 0xf);
-    let correction: u8 = match (high, low) {
-        (0xa..=0xf, 0xa..=0xf) => 0x66,
-        (_, 0xa..=0xf) => 0x06,
-        (0xa..=0xf, _) => 0x66,
-        _ => 0,
-    };
-    let (result, carry) = dst.overflowing_add(correction);
-    *dst = result;
+fn daa(dst: &mut u8, flags: &mut u8) {
+    // N, H and C describe the add or sub that produced the value
+    let subtract = *flags & FLAG_N != 0;
+    let mut correction: u8 = 0;
+    let mut carry = *flags & FLAG_C != 0;
+    if *flags & FLAG_H != 0 || (!subtract && *dst & 0xf > 0x9) {
+        correction |= 0x06;
+    }
+    if carry || (!subtract && *dst > 0x99) {
+        correction |= 0x60;
+        carry = true;
+    }
+    *dst = if subtract { dst.wrapping_sub(correction) } else { dst.wrapping_add(correction) };
 
-    set_flag_if(flags, FLAG_Z, *dst == 0);
-    set_flag(flags, FLAG_N, false);
-    set_flag_if(flags, FLAG_Z, carry);
-    4
+    set_flag(flags, FLAG_Z, *dst == 0);
+    set_flag(flags, FLAG_H, false);
+    set_flag(flags, FLAG_C, carry);
 }
 
-fn ld_rr(dst: &mut u8, src: &u8) -> u16 {
+fn ld_rr(dst: &mut u8, src: &u8) {
     *dst = *src;
-    4
 }
 
-fn ld_rm(dst: &mut u8, src_address: u16, mem: &Memory) -> u16 {
-	*dst = mem.read8(src_address);
-    8
-}
-
-fn bit(val: u8, b: u8, flags: &mut u8, indirect: bool) -> u16 {
+fn bit(val: u8, b: u8, flags: &mut u8) {
     let result = (val >> b) & 1;
     set_flag(flags, FLAG_Z, result == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, true);
-    if indirect { 16 } else { 8 }
 }
 
-fn set(dst: &mut u8, b: u8, indirect: bool) -> u16 {
+fn set(dst: &mut u8, b: u8) {
 	*dst = (1 << b) | *dst;
-    if indirect { 16 } else { 8 }
 }
 
-fn res(dst: &mut u8, b: u8, indirect: bool) -> u16 {
+fn res(dst: &mut u8, b: u8) {
 	*dst = (!(1 << b)) & *dst;
-    if indirect { 16 } else { 8 }
 }
 
-fn swap(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn swap(dst: &mut u8, flags: &mut u8) {
     *dst = (*dst << 4) | (*dst >> 4);
     *flags = if *dst == 0 { FLAG_Z } else { 0 };
-    if indirect { 16 } else { 8 }
 }
 
-fn sla(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn sla(dst: &mut u8, flags: &mut u8) {
     let old_bit7 = *dst & (1 << 7);
     *dst = *dst << 1;
     set_flag(flags, FLAG_Z, *dst == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, old_bit7 != 0);
-    if indirect { 16 } else { 8 }
 }
 
-fn sra(dst: &mut u8, flags: &mut u8, indirect: bool) -> u16 {
+fn sra(dst: &mut u8, flags: &mut u8) {
     let old_bit0 = *dst & 1;
     *dst = ((*dst as i8) >> 1) as u8; // arithmetic shift right
     set_flag(flags, FLAG_Z, *dst == 0);
     set_flag(flags, FLAG_N, false);
     set_flag(flags, FLAG_H, false);
     set_flag(flags, FLAG_C, old_bit0 != 0);
-    if indirect { 16 } else { 8 }
-}
\ No newline at end of file
+}
+/// Conformance against the SingleStepTests SM83 vectors
+/// (https://github.com/SingleStepTests/sm83). Point `SM83_TESTS_DIR` at a
+/// checkout's `v1` directory to run them, otherwise only the built in cases run.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+    use serde_json::Value;
+
+    fn field(state: &Value, name: &str) -> u16 {
+        state[name]
+            .as_u64()
+            .unwrap_or_else(|| panic!("Missing {} in {}", name, state)) as u16
+    }
+
+    fn setup(initial: &Value) -> (CPU, FlatBus) {
+        let mut cpu = CPU::new();
+        let mut mem = FlatBus::new();
+        cpu.pc = field(initial, "pc");
+        cpu.sp = field(initial, "sp");
+        cpu.reg.a = field(initial, "a") as u8;
+        cpu.reg.b = field(initial, "b") as u8;
+        cpu.reg.c = field(initial, "c") as u8;
+        cpu.reg.d = field(initial, "d") as u8;
+        cpu.reg.e = field(initial, "e") as u8;
+        cpu.reg.f = field(initial, "f") as u8;
+        cpu.reg.h = field(initial, "h") as u8;
+        cpu.reg.l = field(initial, "l") as u8;
+        if field(initial, "ime") != 0 {
+            cpu.interrupts.enable();
+        }
+        if let Some(ie) = initial["ie"].as_u64() {
+            mem.data[0xffff] = ie as u8;
+        }
+        for entry in initial["ram"].as_array().unwrap() {
+            mem.data[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+        }
+        (cpu, mem)
+    }
+
+    /// Describe every way the state after one step differs from `case`
+    fn run_case(case: &Value) -> Vec<String> {
+        let (mut cpu, mut mem) = setup(&case["initial"]);
+        let cycles = match cpu.step(&mut mem) {
+            Ok(cycles) => cycles,
+            Err(fault) => return vec![fault.to_string()],
+        };
+
+        let expected = &case["final"];
+        let mut errors = vec![];
+        let mut compare = |name: &str, actual: u16, expected: u16| {
+            if actual != expected {
+                errors.push(format!("{} is {:#06x}, expected {:#06x}", name, actual, expected));
+            }
+        };
+        compare("pc", cpu.pc, field(expected, "pc"));
+        compare("sp", cpu.sp, field(expected, "sp"));
+        compare("a", cpu.reg.a as u16, field(expected, "a"));
+        compare("b", cpu.reg.b as u16, field(expected, "b"));
+        compare("c", cpu.reg.c as u16, field(expected, "c"));
+        compare("d", cpu.reg.d as u16, field(expected, "d"));
+        compare("e", cpu.reg.e as u16, field(expected, "e"));
+        compare("f", cpu.reg.f as u16, field(expected, "f"));
+        compare("h", cpu.reg.h as u16, field(expected, "h"));
+        compare("l", cpu.reg.l as u16, field(expected, "l"));
+        // EI only sets IME after the next instruction, the vectors already show it set
+        let ime = cpu.interrupts.ime || cpu.interrupts.enable_pending();
+        compare("ime", ime as u16, field(expected, "ime"));
+        for entry in expected["ram"].as_array().unwrap() {
+            let address = entry[0].as_u64().unwrap() as u16;
+            let value = entry[1].as_u64().unwrap() as u16;
+            compare(&format!("[{:#06x}]", address), mem.data[address as usize] as u16, value);
+        }
+        let m_cycles = case["cycles"].as_array().unwrap().len() as u16;
+        compare("cycles", cycles, m_cycles * 4);
+        errors
+    }
+
+    #[test]
+    fn built_in_cases() {
+        let cases: Value = serde_json::from_str(r#"[
+            {
+                "name": "f8 ld hl, sp+8 carries out of both nibbles of the low byte",
+                "initial": {"pc": 256, "sp": 4088, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                            "ram": [[256, 248], [257, 8]]},
+                "final": {"pc": 258, "sp": 4088, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 16, "l": 0, "ime": 0,
+                          "ram": [[256, 248], [257, 8]]},
+                "cycles": [null, null, null]
+            },
+            {
+                "name": "e8 add sp, -1",
+                "initial": {"pc": 256, "sp": 65528, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 128, "h": 0, "l": 0, "ime": 0,
+                            "ram": [[256, 232], [257, 255]]},
+                "final": {"pc": 258, "sp": 65527, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 0, "l": 0, "ime": 0,
+                          "ram": [[256, 232], [257, 255]]},
+                "cycles": [null, null, null, null]
+            },
+            {
+                "name": "27 daa after 0x15 + 0x27",
+                "initial": {"pc": 256, "sp": 65534, "a": 60, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                            "ram": [[256, 39]]},
+                "final": {"pc": 257, "sp": 65534, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                          "ram": [[256, 39]]},
+                "cycles": [null]
+            }
+        ]"#).unwrap();
+        for case in cases.as_array().unwrap() {
+            let errors = run_case(case);
+            assert!(errors.is_empty(), "{}: {}", case["name"], errors.join(", "));
+        }
+    }
+
+    /// FlatBus with IE and IF wired to the interrupt lines
+    struct InterruptBus(FlatBus);
+
+    impl Bus for InterruptBus {
+        fn read8(&self, address: u16) -> u8 {
+            self.0.read8(address)
+        }
+
+        fn write8(&mut self, address: u16, value: u8) {
+            self.0.write8(address, value);
+        }
+
+        fn tick(&mut self, _cycles: u16) {}
+    }
+
+    /// A CPU about to run `code` at $0100 with the timer interrupt enabled in IE
+    fn cpu_with_code(code: &[u8]) -> (CPU, InterruptBus) {
+        let mut cpu = CPU::new();
+        let mut mem = InterruptBus(FlatBus::new());
+        mem.0.data[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        mem.0.data[0xffff] = 0x04;
+        cpu.pc = 0x0100;
+        cpu.sp = 0xfffe;
+        (cpu, mem)
+    }
+
+    #[test]
+    fn halt_with_ime_set_wakes_and_dispatches() {
+        // halt; nop
+        let (mut cpu, mut mem) = cpu_with_code(&[0x76, 0x00]);
+        cpu.interrupts.enable();
+        cpu.step(&mut mem).unwrap();
+        assert!(cpu.halt);
+        assert_eq!(cpu.step(&mut mem).unwrap(), 4);
+        assert!(cpu.halt);
+        mem.0.data[0xff0f] = 0x04;
+        assert_eq!(cpu.step(&mut mem).unwrap(), 24);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.read16(&mut mem, 0xfffc), 0x0101);
+        assert_eq!(mem.0.data[0xff0f], 0x00);
+    }
+
+    #[test]
+    fn halt_with_ime_clear_wakes_without_dispatching() {
+        // halt; ld b, $42
+        let (mut cpu, mut mem) = cpu_with_code(&[0x76, 0x06, 0x42]);
+        cpu.step(&mut mem).unwrap();
+        cpu.step(&mut mem).unwrap();
+        assert!(cpu.halt);
+        mem.0.data[0xff0f] = 0x04;
+        cpu.step(&mut mem).unwrap();
+        assert!(!cpu.halt);
+        assert_eq!(cpu.reg.b, 0x42);
+        assert_eq!(cpu.pc, 0x0103);
+        assert_eq!(cpu.sp, 0xfffe);
+        assert_eq!(mem.0.data[0xff0f], 0x04);
+    }
+
+    #[test]
+    fn halt_with_ime_clear_and_an_interrupt_pending_runs_the_next_byte_twice() {
+        // halt; inc b
+        let (mut cpu, mut mem) = cpu_with_code(&[0x76, 0x04]);
+        mem.0.data[0xff0f] = 0x04;
+        cpu.step(&mut mem).unwrap();
+        assert!(!cpu.halt);
+        assert_eq!(cpu.pc, 0x0101);
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0101);
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.reg.b, 2);
+    }
+
+    #[test]
+    fn ei_halt_with_an_interrupt_pending_returns_to_the_halt() {
+        let mut cpu = CPU::new();
+        let mut mem = InterruptBus(FlatBus::new());
+        // ei; halt, and ld b, $42 at the timer vector
+        mem.0.data[0x0100..0x0102].copy_from_slice(&[0xfb, 0x76]);
+        mem.0.data[0x0050..0x0052].copy_from_slice(&[0x06, 0x42]);
+        mem.0.data[0xffff] = 0x04;
+        mem.0.data[0xff0f] = 0x04;
+        cpu.pc = 0x0100;
+        cpu.sp = 0xfffe;
+        for _ in 0..4 {
+            cpu.step(&mut mem).unwrap();
+        }
+        assert_eq!(cpu.read16(&mut mem, 0xfffc), 0x0101);
+        assert_eq!(cpu.reg.b, 0x42);
+        assert_eq!(cpu.pc, 0x0052);
+        assert!(!cpu.halt_bug);
+    }
+
+    #[test]
+    fn single_step_tests() {
+        let dir = match std::env::var("SM83_TESTS_DIR") {
+            Ok(dir) => dir,
+            Err(_) => {
+                eprintln!("SM83_TESTS_DIR not set, skipping the SingleStepTests vectors");
+                return;
+            }
+        };
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("Couldn't read {}: {}", dir, e))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect();
+        paths.sort();
+
+        // one line per opcode, showing its first failing case
+        let mut failures = vec![];
+        for path in paths {
+            let json = std::fs::read_to_string(&path).unwrap();
+            let cases: Value = serde_json::from_str(&json).unwrap();
+            let cases = cases.as_array().unwrap();
+            let mut failed = 0;
+            let mut first = String::new();
+            for case in cases {
+                let errors = run_case(case);
+                if !errors.is_empty() {
+                    if failed == 0 {
+                        first = format!("{}: {}", case["name"], errors.join(", "));
+                    }
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                failures.push(format!("{}/{} failed, {}", failed, cases.len(), first));
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,310 @@
+//! SM83 disassembler producing RGBDS syntax.
+
+use crate::opcodes::{self, FlagEffect, OpInfo, Operand};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub info: &'static OpInfo,
+}
+
+/// Decode the instruction at `address`, `read` can be backed by a live
+/// `Memory` or by a raw ROM bank (see `rom_bank_reader`)
+pub fn decode<F: Fn(u16) -> u8>(read: F, address: u16) -> Instruction {
+    let op = read(address);
+    let info = if op == 0xcb {
+        opcodes::lookup_cb(read(address.wrapping_add(1)))
+    } else {
+        opcodes::lookup(op)
+    };
+    let bytes = (0..info.length as u16)
+        .map(|i| read(address.wrapping_add(i)))
+        .collect();
+    Instruction { address, bytes, info }
+}
+
+/// Reads ROM the way the CPU would see it with `bank` mapped at $4000-$7fff
+pub fn rom_bank_reader(rom: &[u8], bank: usize) -> impl Fn(u16) -> u8 + '_ {
+    move |address| {
+        let offset = match address {
+            0x0000..=0x3fff => address as usize,
+            0x4000..=0x7fff => bank * 0x4000 + (address as usize - 0x4000),
+            _ => return 0xff,
+        };
+        rom.get(offset).copied().unwrap_or(0xff)
+    }
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.len())
+    }
+
+    fn imm8(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    fn imm16(&self) -> u16 {
+        (self.bytes[2] as u16) << 8 | self.bytes[1] as u16
+    }
+
+    /// Destination of a jr, jp, call or rst
+    pub fn target(&self) -> Option<u16> {
+        match self.info.mnemonic {
+            "jr" | "jp" | "call" | "rst" => self.info.operands.iter().find_map(|&operand| match operand {
+                Operand::Rel8 => Some(self.next_address().wrapping_add(self.imm8() as i8 as u16)),
+                Operand::Imm16 => Some(self.imm16()),
+                Operand::Vector(vector) => Some(vector as u16),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_conditional(&self) -> bool {
+        self.info.operands.iter().any(|operand| {
+            matches!(operand, Operand::CondNZ | Operand::CondZ | Operand::CondNC | Operand::CondC)
+        })
+    }
+
+    /// Whether execution can't continue with the next instruction
+    pub fn ends_flow(&self) -> bool {
+        match self.info.mnemonic {
+            "jr" | "jp" | "ret" => !self.is_conditional(),
+            "reti" => true,
+            _ => self.info.is_illegal(),
+        }
+    }
+
+    fn format_operand(&self, operand: Operand) -> String {
+        match operand {
+            Operand::A => "a".to_string(),
+            Operand::B => "b".to_string(),
+            Operand::C => "c".to_string(),
+            Operand::D => "d".to_string(),
+            Operand::E => "e".to_string(),
+            Operand::H => "h".to_string(),
+            Operand::L => "l".to_string(),
+            Operand::AF => "af".to_string(),
+            Operand::BC => "bc".to_string(),
+            Operand::DE => "de".to_string(),
+            Operand::HL => "hl".to_string(),
+            Operand::SP => "sp".to_string(),
+            Operand::IndBC => "[bc]".to_string(),
+            Operand::IndDE => "[de]".to_string(),
+            Operand::IndHL => "[hl]".to_string(),
+            Operand::IndHLI => "[hl+]".to_string(),
+            Operand::IndHLD => "[hl-]".to_string(),
+            Operand::IndC => "[c]".to_string(),
+            Operand::Imm8 => format!("${:02x}", self.imm8()),
+            Operand::Imm16 => format!("${:04x}", self.imm16()),
+            Operand::IndImm8 => format!("[$ff{:02x}]", self.imm8()),
+            Operand::IndImm16 => format!("[${:04x}]", self.imm16()),
+            Operand::Rel8 => format!("${:04x}", self.target().unwrap_or(0)),
+            Operand::SImm8 => format!("{}", self.imm8() as i8),
+            Operand::SPImm8 => {
+                let offset = self.imm8() as i8;
+                if offset < 0 {
+                    format!("sp - {}", -(offset as i16))
+                } else {
+                    format!("sp + {}", offset)
+                }
+            }
+            Operand::CondNZ => "nz".to_string(),
+            Operand::CondZ => "z".to_string(),
+            Operand::CondNC => "nc".to_string(),
+            Operand::CondC => "c".to_string(),
+            Operand::Vector(vector) => format!("${:02x}", vector),
+            Operand::Bit(bit) => format!("{}", bit),
+        }
+    }
+
+    fn format_bytes(&self, separator: &str) -> String {
+        self.bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    /// Effect on Z, N, H and C written as in most opcode tables, e.g. "Z0H-"
+    pub fn flags(&self) -> String {
+        self.info
+            .flags
+            .iter()
+            .zip("ZNHC".chars())
+            .map(|(effect, name)| match effect {
+                FlagEffect::Unaffected => '-',
+                FlagEffect::Reset => '0',
+                FlagEffect::Set => '1',
+                FlagEffect::Affected => name,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // rgbasm always emits a 0 after stop, and can't emit illegal opcodes at all
+        if self.info.is_illegal() || (self.info.mnemonic == "stop" && self.bytes[1] != 0) {
+            return write!(f, "db ${}", self.format_bytes(", $"));
+        }
+        write!(f, "{}", self.info.mnemonic)?;
+        for (i, &operand) in self.info.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, self.format_operand(operand))?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode every instruction in `start..=end`. An instruction running past
+/// `end` is cut short and its bytes emitted as data.
+pub fn disassemble_range<F: Fn(u16) -> u8>(read: F, start: u16, end: u16) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let mut instruction = decode(&read, address as u16);
+        let remaining = end as u32 - address + 1;
+        if (instruction.len() as u32) > remaining {
+            instruction.bytes.truncate(remaining as usize);
+            instruction.info = &opcodes::ILLEGAL;
+        }
+        address += instruction.len() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Decode the function at `start`, following conditional and unconditional
+/// jumps that stay within `start..=end` until every path returns or leaves it.
+/// Calls aren't followed.
+pub fn disassemble_function<F: Fn(u16) -> u8>(read: F, start: u16, end: u16) -> Vec<Instruction> {
+    let mut found: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut pending = vec![start];
+    while let Some(address) = pending.pop() {
+        if address < start || address > end || found.contains_key(&address) {
+            continue;
+        }
+        let instruction = decode(&read, address);
+        if instruction.info.mnemonic == "jr" || instruction.info.mnemonic == "jp" {
+            if let Some(target) = instruction.target() {
+                pending.push(target);
+            }
+        }
+        if !instruction.ends_flow() {
+            pending.push(instruction.next_address());
+        }
+        found.insert(address, instruction);
+    }
+
+    // fill gaps (and overlapping decodes) with data so the output reassembles
+    // to the original bytes
+    let mut instructions: Vec<Instruction> = vec![];
+    let mut address = start as u32;
+    for (_, instruction) in found {
+        if (instruction.address as u32) < address {
+            continue;
+        }
+        if (instruction.address as u32) > address {
+            instructions.extend(disassemble_data(&read, address as u16, instruction.address - 1));
+        }
+        address = instruction.address as u32 + instruction.len() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+fn disassemble_data<F: Fn(u16) -> u8>(read: F, start: u16, end: u16) -> Vec<Instruction> {
+    (start..=end)
+        .map(|address| Instruction {
+            address,
+            bytes: vec![read(address)],
+            info: &opcodes::ILLEGAL,
+        })
+        .collect()
+}
+
+/// One line of listing: the instruction followed by a comment with its bank,
+/// address and encoding, plus timing and flags when `verbose` is set
+pub fn format_line(instruction: &Instruction, bank: usize, verbose: bool) -> String {
+    let mut line = format!(
+        "    {:<24}; {:02x}:{:04x}  {}",
+        instruction.to_string(),
+        bank,
+        instruction.address,
+        instruction.format_bytes(" ")
+    );
+    if verbose && !instruction.info.is_illegal() {
+        let info = instruction.info;
+        let cycles = if info.cycles == info.cycles_taken {
+            format!("{}", info.cycles)
+        } else {
+            format!("{}/{}", info.cycles_taken, info.cycles)
+        };
+        line = format!("{:<52}{:>5}  {}", line, cycles, instruction.flags());
+    }
+    line
+}
+
+/// RGBDS section header placing the following listing at `address` of `bank`
+pub fn section_header(bank: usize, address: u16) -> String {
+    if address < 0x4000 {
+        format!("SECTION \"ROM0 ${:04x}\", ROM0[${:04x}]", address, address)
+    } else {
+        format!(
+            "SECTION \"ROMX ${:02x}:{:04x}\", ROMX[${:04x}], BANK[${:02x}]",
+            bank, address, address, bank
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `bytes` placed at $0150, one string per instruction
+    fn listing(bytes: &[u8]) -> Vec<String> {
+        // like a ROM, decoding can read past the end of the range
+        let read = |address: u16| bytes.get((address - 0x0150) as usize).copied().unwrap_or(0xff);
+        disassemble_range(read, 0x0150, 0x0150 + bytes.len() as u16 - 1)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn jr_shows_its_target() {
+        // forwards, backwards and onto itself
+        assert_eq!(listing(&[0x18, 0x02, 0x20, 0xfc, 0x38, 0xfe]), ["jr $0154", "jr nz, $0150", "jr c, $0154"]);
+    }
+
+    #[test]
+    fn ldh_and_sp_offsets() {
+        assert_eq!(
+            listing(&[0xe0, 0x47, 0xf0, 0x44, 0xe2, 0xf8, 0xfe, 0xf8, 0x05, 0xe8, 0x80]),
+            ["ldh [$ff47], a", "ldh a, [$ff44]", "ldh [c], a", "ld hl, sp - 2", "ld hl, sp + 5", "add sp, -128"]
+        );
+    }
+
+    #[test]
+    fn cb_prefixed_ops() {
+        assert_eq!(
+            listing(&[0xcb, 0x7c, 0xcb, 0x37, 0xcb, 0x86, 0xcb, 0xff]),
+            ["bit 7, h", "swap a", "res 0, [hl]", "set 7, a"]
+        );
+    }
+
+    #[test]
+    fn illegal_and_truncated_bytes_are_data() {
+        // an illegal opcode, then ld bc, n16 missing its last byte
+        assert_eq!(listing(&[0xd3, 0x00, 0x01, 0x34]), ["db $d3", "nop", "db $01, $34"]);
+        assert_eq!(listing(&[0x10, 0x01]), ["db $10, $01"]);
+    }
+}
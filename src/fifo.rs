@@ -0,0 +1,211 @@
+//! Dot by dot mode 3: a fetcher reads BG and window tiles 8 pixels at a time
+//! into a FIFO that's shifted out one pixel per dot, while sprites are
+//! fetched into a second FIFO as the output reaches them. Mode 3 takes as
+//! long as that does, 172 dots plus SCX fine scroll, window start and sprite
+//! fetch stalls. A line is only drawn as far as the CPU has got, so register
+//! writes during mode 3 show up from the pixel they happened at.
+
+use crate::gpu::{SpritePixel, GPU, LCDC_SHOW_BG, LCDC_SHOW_SPRITES, MAX_SPRITES_PER_LINE, SCREEN_WIDTH};
+use crate::memory::Memory;
+use std::collections::VecDeque;
+
+/// Dots it takes to read a tile number and both bytes of its tile data, the
+/// fetcher pushes on the last of them if the FIFO is empty
+const FETCH_DOTS: i16 = 6;
+
+/// Dots a sprite fetch takes once the BG fetcher is out of the way
+const SPRITE_FETCH_DOTS: u16 = 6;
+
+struct Fetcher {
+    /// Dots into the current fetch
+    dots: i16,
+    /// Tile column of the next fetch, counted from the left of the screen or window
+    column: u8,
+    window: bool,
+}
+
+impl Fetcher {
+    /// Tile row for the next 8 pixels as color indices
+    fn fetch(&self, mem: &Memory, lcdc: u8) -> [u8; 8] {
+        let mut pixels = [0; 8];
+        // with BG off a DMG fetches as usual but shows color 0
+        if lcdc & LCDC_SHOW_BG == 0 {
+            return pixels;
+        }
+        let (map, x, y) = if self.window {
+            (GPU::window_map(lcdc), self.column & 0x1f, mem.gpu.window_line())
+        } else {
+            let scx = mem.read8(0xff43);
+            let y = mem.read8(0xff44).wrapping_add(mem.read8(0xff42));
+            (GPU::bg_map(lcdc), (scx / 8 + self.column) & 0x1f, y)
+        };
+        let tile_id = mem.read8(map + (y as u16 / 8) * 32 + x as u16);
+        let row_addr = GPU::get_tile_addr(lcdc, tile_id) + (y as u16 % 8) * 2;
+        let (low, high) = (mem.read8(row_addr), mem.read8(row_addr + 1));
+        for (bit, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = GPU::tile_color(low, high, bit as u8);
+        }
+        pixels
+    }
+}
+
+/// Mode 3 of line LY, as far as it's been drawn
+pub struct Line {
+    /// Picked by OAM scan, whether they're shown is up to LCDC as they're reached
+    sprites: [u16; MAX_SPRITES_PER_LINE],
+    count: usize,
+    sprite_fetched: [bool; MAX_SPRITES_PER_LINE],
+    bg_fifo: VecDeque<u8>,
+    /// Lined up with the output, None where no sprite pixel is showing
+    sprite_fifo: VecDeque<Option<SpritePixel>>,
+    fetcher: Fetcher,
+    /// SCX fine scroll is applied by dropping pixels off the front
+    discard: usize,
+    /// Dots left of a sprite fetch that's holding up the output
+    stall: u16,
+    x: usize,
+    /// Dots of mode 3 run so far
+    dots: u16,
+}
+
+impl Line {
+    pub fn start(mem: &Memory) -> Line {
+        let (sprites, count) = GPU::line_sprites(mem, mem.read8(0xff44), mem.read8(0xff40));
+        Line {
+            sprites,
+            count,
+            sprite_fetched: [false; MAX_SPRITES_PER_LINE],
+            bg_fifo: VecDeque::with_capacity(8),
+            sprite_fifo: VecDeque::with_capacity(8),
+            // the first fetch of a line is thrown away
+            fetcher: Fetcher { dots: -FETCH_DOTS, column: 0, window: false },
+            discard: (mem.read8(0xff43) % 8) as usize,
+            stall: 0,
+            x: 0,
+            dots: 0,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.x == SCREEN_WIDTH
+    }
+
+    /// Each pixel takes at least a dot, so the line can't be done in fewer
+    pub fn pixels_left(&self) -> u16 {
+        (SCREEN_WIDTH - self.x) as u16
+    }
+
+    /// Run until `dots` into mode 3 or the end of the line, whichever is first
+    pub fn run_until(&mut self, mem: &mut Memory, dots: u16) {
+        while self.dots < dots && !self.done() {
+            self.step(mem);
+        }
+        if self.done() && self.fetcher.window {
+            mem.gpu.next_window_line();
+            self.fetcher.window = false;
+        }
+    }
+
+    fn step(&mut self, mem: &mut Memory) {
+        self.dots += 1;
+        if self.stall > 0 {
+            self.stall -= 1;
+            return;
+        }
+        let lcdc = mem.read8(0xff40);
+        let ly = mem.read8(0xff44);
+        let x = self.x;
+
+        // a sprite starting at x stops the output until it's been fetched,
+        // first waiting for the BG fetcher to finish reading its tile
+        let sprite = (0..self.count).find(|&i| !self.sprite_fetched[i] && mem.read8(self.sprites[i] + 1) as usize <= x + 8);
+        if let Some(i) = sprite.filter(|_| lcdc & LCDC_SHOW_SPRITES != 0 && self.discard == 0) {
+            if self.fetcher.dots < FETCH_DOTS - 1 {
+                self.fetcher.dots += 1;
+                return;
+            }
+            self.sprite_fetched[i] = true;
+            self.stall = SPRITE_FETCH_DOTS - 1;
+            let left = mem.read8(self.sprites[i] + 1) as usize;
+            for (i, &pixel) in GPU::sprite_pixels(mem, self.sprites[i], ly, lcdc).iter().enumerate() {
+                // the part left of the screen is cut off
+                if left + i < x + 8 {
+                    continue;
+                }
+                let position = left + i - x - 8;
+                while self.sprite_fifo.len() <= position {
+                    self.sprite_fifo.push_back(None);
+                }
+                // earlier sprites win, color 0 lets later ones through
+                if self.sprite_fifo[position].is_none() && pixel.color != 0 {
+                    self.sprite_fifo[position] = Some(pixel);
+                }
+            }
+            return;
+        }
+
+        let wx = mem.read8(0xff4b) as usize;
+        let window_visible = lcdc & LCDC_SHOW_BG != 0 && GPU::window_visible(mem, lcdc);
+        if window_visible && !self.fetcher.window && x + 7 >= wx {
+            // the BG pixels fetched so far are dropped and fetching restarts
+            // from the window's first tile
+            self.bg_fifo.clear();
+            self.fetcher = Fetcher { dots: 0, column: 0, window: true };
+            self.discard = 7usize.saturating_sub(wx);
+        } else if let Some(bg) = self.bg_fifo.pop_front() {
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                GPU::mix_pixel(mem, x, bg, self.sprite_fifo.pop_front().flatten());
+                self.x += 1;
+            }
+        }
+
+        self.fetcher.dots += 1;
+        if self.fetcher.dots >= FETCH_DOTS && self.bg_fifo.is_empty() {
+            self.bg_fifo.extend(self.fetcher.fetch(mem, lcdc));
+            self.fetcher.column += 1;
+            self.fetcher.dots = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::gpu::{Renderer, SCREEN_WIDTH};
+    use crate::memory::Memory;
+
+    /// Length of HBlank and the pixels of line 0
+    fn draw_line_0(renderer: Renderer) -> (u16, Vec<u8>) {
+        let mut mem = Memory::with_cartridge(Cartridge::with_code(0x00, 0, &[]));
+        mem.gpu.renderer = renderer;
+        // a striped BG tile everywhere, a solid sprite at the left edge of line 0
+        mem.data[0x8000..0x8010].fill(0x0f);
+        mem.data[0x8010..0x8020].fill(0xff);
+        mem.data[0xfe00..0xfe04].copy_from_slice(&[16, 8, 0x01, 0x00]);
+        mem.write8(0xff40, 0x93);
+        mem.write8(0xff43, 0x03);
+        mem.write8(0xff47, 0xe4);
+        mem.write8(0xff48, 0x1b);
+        mem.data[0xff44] = 153;
+        mem.step_ppu();
+        mem.step_ppu();
+        // the pixel FIFO takes a few steps to get through mode 3
+        let mut hblank = mem.step_ppu();
+        while mem.read8(0xff41) & 0x03 == 0x03 {
+            hblank = mem.step_ppu();
+        }
+        (hblank, mem.gpu.frame[..SCREEN_WIDTH * 2].to_vec())
+    }
+
+    #[test]
+    fn pixel_fifo_stretches_mode_3_and_draws_the_same_line() {
+        let (hblank, scanline) = draw_line_0(Renderer::Scanline);
+        assert_eq!(hblank, 204);
+        let (hblank, fifo) = draw_line_0(Renderer::PixelFifo);
+        // 172 dots, 3 for SCX and 8 for the sprite, HBlank is that much shorter
+        assert_eq!(hblank, 376 - 183);
+        assert_eq!(fifo, scanline);
+    }
+}
@@ -1,40 +1,113 @@
-use crate::cpu::{Interrupt, CPU};
-use crate::gpu::GPU;
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu::{Fault, CPU};
+use crate::interrupt::Interrupt;
 use crate::memory::Memory;
+use crate::scheduler::{Event, Scheduler};
 use memmap::MmapOptions;
-use std::ffi::CStr;
 use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Used when no boot ROM file is given, built from boot/dmg_boot.asm
+static DMG_BOOT_ROM: &[u8] = include_bytes!("../boot/dmg_boot.bin");
+
+/// Normal speed T-cycles between updates of the cartridge clock
+const RTC_TICK: u64 = 0x10000;
 
 pub struct GB {
     pub rom_path: String,
     pub rom_title: String,
+    /// Where battery-backed RAM is kept, the ROM's path with a .sav extension
+    /// unless a save directory is set
+    pub save_path: PathBuf,
     pub mem: Memory,
     pub cpu: CPU,
-    gpu: GPU,
+    pub scheduler: Scheduler,
+    /// Called with the new state whenever a rumble cart's motor turns on or off
+    pub on_rumble: Option<Box<dyn FnMut(bool)>>,
+    rumbling: bool,
+}
+
+/// The CPU's view of the system, memory plus the scheduler that runs the rest
+/// of the hardware as bus cycles go by
+struct System<'a> {
+    mem: &'a mut Memory,
+    scheduler: &'a mut Scheduler,
 }
 
 impl GB {
-    pub fn with_rom(path: &str) -> GB {
-        let rom_file = File::open(path).unwrap();
-        let rom = unsafe { MmapOptions::new().map(&rom_file).unwrap() };
-        let title_ptr = &rom[0x0134..0x144];
+    pub fn with_rom(path: &str) -> Result<GB, String> {
+        let rom_file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
+        let rom = unsafe { MmapOptions::new().map(&rom_file) }.map_err(|e| format!("Couldn't map {}: {}", path, e))?;
+        let cartridge = Cartridge::new(Box::new(rom)).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(GB::new(cartridge, path))
+    }
+
+    /// `path` is where the cartridge was loaded from, saves go next to it
+    pub fn new(cartridge: Cartridge, path: &str) -> GB {
+        let rom_title = cartridge.header.title.clone();
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Event::PPU, 456);
+        if cartridge.has_rtc() {
+            scheduler.schedule(Event::RTC, RTC_TICK);
+        }
+        let mut mem = Memory::with_cartridge(cartridge);
+        mem.boot_rom = DMG_BOOT_ROM.to_vec();
         GB {
             rom_path: path.to_string(),
-            rom_title: unsafe { CStr::from_ptr(title_ptr.as_ptr() as *const i8) }
-                .to_str()
-                .unwrap()
-                .to_owned(),
-            mem: Memory::with_rom(rom),
+            rom_title,
+            save_path: PathBuf::from(path).with_extension("sav"),
+            mem,
             cpu: CPU::new(),
-            gpu: GPU::new(),
+            scheduler,
+            on_rumble: None,
+            rumbling: false,
+        }
+    }
+
+    /// Keep the .sav file in `dir` instead of next to the ROM
+    pub fn set_save_dir(&mut self, dir: &Path) {
+        let file_name = Path::new(&self.rom_path).file_name().unwrap();
+        self.save_path = dir.join(file_name).with_extension("sav");
+    }
+
+    /// Load battery-backed RAM from the .sav file, if there is one
+    pub fn load_save(&mut self) {
+        if !self.mem.cartridge.has_battery() {
+            return;
         }
+        match std::fs::read(&self.save_path) {
+            Ok(data) => self.mem.cartridge.load_save_data(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            // carrying on would overwrite the save on exit
+            Err(e) => panic!("Couldn't read {}: {}", self.save_path.display(), e),
+        }
+    }
+
+    /// Whether there's battery-backed RAM that changed since the last save
+    pub fn save_pending(&self) -> bool {
+        self.mem.cartridge.has_battery() && self.mem.cartridge.ram_dirty
+    }
+
+    /// Write battery-backed RAM to the .sav file
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.mem.cartridge.has_battery() {
+            return Ok(());
+        }
+        // write next to it first so a crash halfway doesn't lose the old save
+        let temp_path = self.save_path.with_extension("sav.tmp");
+        std::fs::write(&temp_path, self.mem.cartridge.save_data())?;
+        std::fs::rename(&temp_path, &self.save_path)?;
+        self.mem.cartridge.ram_dirty = false;
+        Ok(())
     }
 
     pub fn set_joypad(&mut self, directional: usize, button: u8) {
         let mask = 1 << button;
         if self.mem.joypad_states[directional] & mask != 0 {
             self.mem.joypad_states[directional] &= !mask;
-            self.cpu.set_interrupt(&mut self.mem, Interrupt::JoyPad);
+            self.mem.request_interrupt(Interrupt::JoyPad);
         }
     }
 
@@ -42,35 +115,372 @@ impl GB {
         self.mem.joypad_states[directional] |= 1 << button;
     }
 
-    pub fn step(&mut self, buf: &mut [u8]) -> (u16, bool) {
-        let cycles = self.cpu.step(&mut self.mem);
-        let (redraw, vblank) = self.gpu.step(&mut self.mem, cycles);
+    pub fn step(&mut self, buf: &mut [u8]) -> Result<(u16, bool), Fault> {
+        // the CPU advances the rest of the system through the bus, see `Timing`
+        let mut system = System { mem: &mut self.mem, scheduler: &mut self.scheduler };
+        let cycles = if self.cpu.halt && system.pending_interrupts() == 0 {
+            // only an event can end HALT, skip straight to the next one
+            let until = system.scheduler.next_deadline() - system.scheduler.now();
+            let cycles = (until.min(0xfff0) as u16 + 3) & !3;
+            system.tick(cycles);
+            cycles
+        } else {
+            self.cpu.step(&mut system)?
+        };
 
-        if vblank {
-            self.cpu.set_interrupt(&mut self.mem, Interrupt::VBlank);
+        let rumbling = self.mem.cartridge.rumble();
+        if rumbling != self.rumbling {
+            self.rumbling = rumbling;
+            if let Some(on_rumble) = &mut self.on_rumble {
+                on_rumble(rumbling);
+            }
         }
 
+        let redraw = self.mem.gpu.frame_ready;
         if redraw {
-            self.gpu.draw_screen(&mut self.mem, buf);
+            self.mem.gpu.frame_ready = false;
+            if let Some(tracer) = &mut self.cpu.tracer {
+                // frames are counted from when the cartridge starts running
+                if !self.mem.boot_rom_mapped() {
+                    tracer.end_frame();
+                }
+            }
+            buf.copy_from_slice(&self.mem.gpu.frame);
         }
 
-        (cycles, redraw)
+        Ok((cycles, redraw))
     }
 
+    /// Whether a rumble cart's motor is on
+    pub fn rumble(&self) -> bool {
+        self.rumbling
+    }
+
+    /// Replace the built-in boot ROM with a DMG boot ROM dump
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        if boot_rom.len() != 0x100 {
+            return Err(format!("DMG boot ROMs are 256 bytes, this one is {}", boot_rom.len()));
+        }
+        self.mem.boot_rom = boot_rom;
+        Ok(())
+    }
+
+    /// Power on, the boot ROM sets up everything else
     pub fn reset(&mut self) {
-        self.cpu.reg.a = 0x01;
-        self.cpu.reg.f = 0xb0;
-        self.cpu.reg.c = 0x13;
-        self.cpu.reg.e = 0xd8;
-        self.cpu.reg.h = 0x01;
-        self.cpu.reg.h = 0x4d;
+        self.cpu.pc = 0x0000;
+        self.mem.map_boot_rom();
 
         self.mem.joypad_states[0] = 0x0f;
         self.mem.joypad_states[1] = 0x0f;
+    }
+}
+
+impl System<'_> {
+    /// Handle `event` that was due `at`, periodic ones are rescheduled from
+    /// then rather than now so they don't drift when handled late
+    fn dispatch(&mut self, event: Event, at: u64) {
+        match event {
+            Event::PPU => {
+                let cycles = self.mem.step_ppu();
+                self.scheduler.schedule_at(Event::PPU, at + cycles as u64);
+            }
+            Event::Timer => {
+                self.sync_timer();
+                self.schedule_timer();
+            }
+            Event::Serial => self.mem.finish_serial_transfer(),
+            Event::RTC => {
+                // the clock has its own crystal, it doesn't speed up with the CPU
+                self.mem.cartridge.tick_rtc(RTC_TICK);
+                let cycles = if self.mem.double_speed() { RTC_TICK * 2 } else { RTC_TICK };
+                self.scheduler.schedule_at(Event::RTC, at + cycles);
+            }
+        }
+    }
+
+    fn sync_ppu(&mut self) {
+        if let Some(at) = self.scheduler.deadline(Event::PPU) {
+            self.mem.sync_ppu(at.saturating_sub(self.scheduler.now()));
+        }
+    }
+
+    fn sync_timer(&mut self) {
+        if self.mem.timer.sync(self.scheduler.now()) {
+            self.mem.request_interrupt(Interrupt::Timer);
+        }
+    }
+
+    fn schedule_timer(&mut self) {
+        match self.mem.timer.next_interrupt() {
+            Some(at) => self.scheduler.schedule_at(Event::Timer, at),
+            None => self.scheduler.cancel(Event::Timer),
+        }
+    }
+}
+
+impl Bus for System<'_> {
+    fn read8(&self, address: u16) -> u8 {
+        if self.mem.dma_blocks(address, self.scheduler.now()) {
+            return 0xff;
+        }
+        match address {
+            // the timer is only synced on its events and writes
+            0xff04..=0xff07 => self.mem.timer.synced(self.scheduler.now()).read(address),
+            _ => self.mem.read8(address),
+        }
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        if self.mem.dma_blocks(address, self.scheduler.now()) {
+            return;
+        }
+        match address {
+            0xff04..=0xff07 => {
+                self.sync_timer();
+                self.mem.timer.write(address, value);
+                self.schedule_timer();
+            }
+            0xff02 => {
+                self.mem.write8(address, value);
+                // 8 bits at 8192 Hz when started on the internal clock
+                if value & 0x81 == 0x81 {
+                    self.scheduler.schedule(Event::Serial, 8 * 512);
+                }
+            }
+            0xff46 => {
+                self.mem.write8(address, value);
+                self.mem.start_dma(value, self.scheduler.now());
+            }
+            0xff40..=0xff4b => {
+                // the PPU has to have drawn up to now with the old value
+                self.sync_ppu();
+                self.mem.write8(address, value);
+            }
+            _ => self.mem.write8(address, value),
+        }
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        self.scheduler.advance(cycles as u64);
+        self.mem.sync_dma(self.scheduler.now());
+        while let Some((event, at)) = self.scheduler.pop_due() {
+            self.dispatch(event, at);
+        }
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        // the interrupt lines don't go through the bus DMA is holding
+        self.mem.read8(0xffff) & self.mem.read8(0xff0f) & 0x1f
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.mem.acknowledge_interrupt(interrupt);
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.mem.joypad_lines()
+    }
+
+    fn stop(&mut self) -> bool {
+        // STOP always resets DIV
+        self.sync_timer();
+        self.mem.timer.reset_div();
+        self.schedule_timer();
+        if self.mem.speed_switch_armed() {
+            self.mem.switch_speed();
+            return true;
+        }
+        false
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.mem.rom_bank()
+    }
+
+    fn boot_rom_mapped(&self) -> bool {
+        self.mem.boot_rom_mapped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn gb_with_code(kind: u8, ram_size: u8, code: &[u8]) -> GB {
+        GB::new(Cartridge::with_code(kind, ram_size, code), "test.gb")
+    }
+
+    /// A CGB cartridge running `code` at $0100
+    fn cgb_with_code(code: &[u8]) -> GB {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        GB::new(Cartridge::new(Box::new(rom)).unwrap(), "test.gb")
+    }
 
-        *self.mem.reg_lcdc() = 0x91;
-        self.mem.data[0xff47] = 0xfc;
-        self.mem.data[0xff48] = 0xff;
-        self.mem.data[0xff49] = 0xff;
+    fn div(gb: &GB) -> u8 {
+        gb.mem.timer.synced(gb.scheduler.now()).read(0xff04)
+    }
+
+    #[test]
+    fn rumble_follows_the_motor_bit() {
+        // ld a, $08; ld [$4000], a; xor a; ld [$4000], a; jr @
+        let mut gb = gb_with_code(0x1c, 0, &[0x3e, 0x08, 0xea, 0x00, 0x40, 0xaf, 0xea, 0x00, 0x40, 0x18, 0xfe]);
+        let changes = Rc::new(Cell::new(0));
+        let callback_changes = changes.clone();
+        gb.on_rumble = Some(Box::new(move |_| callback_changes.set(callback_changes.get() + 1)));
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        gb.step(&mut buf).unwrap();
+        gb.step(&mut buf).unwrap();
+        assert!(gb.rumble());
+        gb.step(&mut buf).unwrap();
+        gb.step(&mut buf).unwrap();
+        assert!(!gb.rumble());
+        assert_eq!(changes.get(), 2);
+    }
+
+    #[test]
+    fn frames_are_70224_cycles_apart_when_events_fire_late() {
+        // call $0105; jr $0100; ret, 24 + 16 + 12 cycles a loop
+        let mut gb = gb_with_code(0x00, 0, &[0xcd, 0x05, 0x01, 0x18, 0xfb, 0xc9]);
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        let mut frames = vec![];
+        while frames.len() < 11 {
+            if gb.step(&mut buf).unwrap().1 {
+                frames.push(gb.scheduler.now());
+            }
+        }
+        // each frame is seen at the end of an instruction, up to 24 cycles late
+        let elapsed = frames[10] - frames[0];
+        assert!(elapsed.abs_diff(10 * 70224) < 24, "10 frames took {} cycles", elapsed);
+    }
+
+    #[test]
+    fn pixel_fifo_sees_writes_during_mode_3() {
+        // wait for mode 3 of line 64, then xor a; ldh [$47], a; jr @
+        let mut gb = gb_with_code(0x00, 0, &[
+            0xf0, 0x41, 0xe6, 0x03, 0xfe, 0x03, 0x20, 0xf8,
+            0xf0, 0x44, 0xfe, 0x40, 0x20, 0xf2,
+            0xaf, 0xe0, 0x47, 0x18, 0xfe,
+        ]);
+        gb.mem.gpu.renderer = crate::gpu::Renderer::PixelFifo;
+        // every tile is solid color 3, BGP turns it from black to white
+        gb.mem.data[0x8000..0x8010].fill(0xff);
+        gb.mem.write8(0xff40, 0x91);
+        gb.mem.write8(0xff47, 0xe4);
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        while !gb.step(&mut buf).unwrap().1 {}
+        let pixel = |x: usize, y: usize| buf[(y * SCREEN_WIDTH + x) * 2];
+        assert_eq!(pixel(159, 63), pixel(0, 64));
+        assert_ne!(pixel(0, 64), pixel(159, 64));
+        assert_eq!(pixel(159, 64), pixel(0, 65));
+    }
+
+    #[test]
+    fn stop_resets_div_and_waits_for_a_joypad_line() {
+        // ld b, 0; dec b; jr nz, -3; stop; inc b
+        let mut gb = gb_with_code(0x00, 0, &[0x06, 0x00, 0x05, 0x20, 0xfd, 0x10, 0x00, 0x04]);
+        // nothing held, reset would do this
+        gb.mem.joypad_states = [0x0f, 0x0f];
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        while gb.cpu.pc != 0x0105 {
+            gb.step(&mut buf).unwrap();
+        }
+        assert_ne!(div(&gb), 0);
+        gb.step(&mut buf).unwrap();
+        // the second byte of STOP is skipped
+        assert!(gb.cpu.stopped);
+        assert_eq!(gb.cpu.pc, 0x0107);
+        assert_eq!(div(&gb), 0);
+        for _ in 0..4 {
+            gb.step(&mut buf).unwrap();
+        }
+        assert!(gb.cpu.stopped);
+        assert_eq!(gb.cpu.reg.b, 0);
+
+        // select the buttons and press start
+        gb.mem.write8(0xff00, 0x10);
+        gb.set_joypad(0, 3);
+        gb.step(&mut buf).unwrap();
+        assert!(!gb.cpu.stopped);
+        assert_eq!(gb.cpu.reg.b, 1);
+    }
+
+    #[test]
+    fn armed_stop_switches_speed_and_the_ppu_keeps_its_pace() {
+        // ld a, $01; ldh [$4d], a; stop; jr @
+        let mut gb = cgb_with_code(&[0x3e, 0x01, 0xe0, 0x4d, 0x10, 0x00, 0x18, 0xfe]);
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        for _ in 0..3 {
+            gb.step(&mut buf).unwrap();
+        }
+        assert!(!gb.cpu.stopped);
+        assert_eq!(gb.cpu.pc, 0x0106);
+        assert!(gb.mem.double_speed());
+        // the armed bit is cleared, the rest reads as 1
+        assert_eq!(gb.mem.read8(0xff4d), 0xfe);
+
+        let mut frames = vec![];
+        while frames.len() < 3 {
+            if gb.step(&mut buf).unwrap().1 {
+                frames.push((gb.scheduler.now(), div(&gb)));
+            }
+        }
+        // a frame takes twice the CPU cycles, DIV still counts CPU cycles
+        let elapsed = frames[2].0 - frames[1].0;
+        assert!(elapsed.abs_diff(2 * 70224) < 24, "a frame took {} cycles", elapsed);
+        // 548 or 549 DIV ticks, wrapped around twice
+        let div_ticks = frames[2].1.wrapping_sub(frames[1].1);
+        assert!(matches!(div_ticks, 36 | 37), "DIV counted {} in a frame", div_ticks);
+    }
+
+    #[test]
+    fn battery_ram_round_trips_through_sav_file() {
+        // ld a, $0a; ld [$0000], a; ld a, $42; ld [$a123], a; jr @
+        let code = [0x3e, 0x0a, 0xea, 0x00, 0x00, 0x3e, 0x42, 0xea, 0x23, 0xa1, 0x18, 0xfe];
+        let with_save_in_temp_dir = || {
+            let mut gb = GB::new(Cartridge::with_code(0x03, 0x02, &code), "mcugb-battery.gb");
+            gb.set_save_dir(&std::env::temp_dir());
+            gb
+        };
+        let mut gb = with_save_in_temp_dir();
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        for _ in 0..4 {
+            gb.step(&mut buf).unwrap();
+        }
+        assert!(gb.save_pending());
+        gb.save().unwrap();
+        assert!(!gb.save_pending());
+        assert_eq!(std::fs::read(&gb.save_path).unwrap().len(), 0x2000);
+
+        let mut loaded = with_save_in_temp_dir();
+        loaded.load_save();
+        assert_eq!(loaded.mem.cartridge.save_data()[0x0123], 0x42);
+        std::fs::remove_file(&gb.save_path).unwrap();
+    }
+
+    #[test]
+    fn boot_roms_have_to_be_256_bytes() {
+        let mut gb = gb_with_code(0x00, 0, &[]);
+        assert!(gb.set_boot_rom(vec![0; 0x200]).is_err());
+        assert!(gb.set_boot_rom(vec![0; 0x100]).is_ok());
+    }
+
+    #[test]
+    fn built_in_boot_rom_hands_over_with_dmg_state() {
+        let mut gb = gb_with_code(0x00, 0, &[0x18, 0xfe]);
+        gb.reset();
+        let mut buf = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+        while gb.mem.boot_rom_mapped() {
+            gb.step(&mut buf).unwrap();
+            assert!(gb.scheduler.now() < 2 * 4194304, "boot ROM didn't finish");
+        }
+        assert_eq!(gb.cpu.pc, 0x0100);
+        assert_eq!(gb.cpu.sp, 0xfffe);
+        assert_eq!([gb.cpu.af(), gb.cpu.bc(), gb.cpu.de(), gb.cpu.hl()], [0x01b0, 0x0013, 0x00d8, 0x014d]);
+        assert_eq!(gb.mem.read8(0x0000), 0x00);
     }
 }
@@ -1,5 +1,6 @@
+use crate::fifo;
+use crate::interrupt::Interrupt;
 use crate::memory::Memory;
-use std::convert::TryInto;
 
 // static LCDC_ON: u8 = 1 << 7;
 static LCDC_WINDOW_TILE_MAP_SELECT: u8 = 1 << 6;
@@ -7,16 +8,34 @@ static LCDC_WINDOW_ON: u8 = 1 << 5;
 static LCDC_BG_TILE_DATA: u8 = 1 << 4;
 static LCDC_BG_TILE_MAP_SELECT: u8 = 1 << 3;
 static LCDC_SPRITE_DOUBLE_HEIGHT: u8 = 1 << 2;
-static LCDC_SHOW_SPRITES: u8 = 1 << 1;
-// static LCDC_SHOW_BG: u8 = 1 << 0;
+pub static LCDC_SHOW_SPRITES: u8 = 1 << 1;
+pub static LCDC_SHOW_BG: u8 = 1 << 0;
 
 
-// static SPRITE_PRIORITY: u8 = 1 << 7;
+static SPRITE_PRIORITY: u8 = 1 << 7;
 static SPRITE_FLIP_V:u8 = 1 << 6;
 static SPRITE_FLIP_H: u8 = 1 << 5;
+static SPRITE_PALETTE: u8 = 1 << 4;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const MAX_SPRITES_PER_LINE: usize = 10;
 
 static COLORS: [(u8, u8); 4] = [(0xe7, 0x9c), (0x97, 0x08), (0x44, 0x31), (0x31, 0x6a)];
 
+/// A sprite's pixel before it goes through its palette
+#[derive(Clone, Copy)]
+pub struct SpritePixel {
+    /// 0 is transparent
+    pub color: u8,
+    /// Behind BG and window colors 1-3
+    pub behind_bg: bool,
+    /// OBP0 or OBP1
+    pub palette: u16,
+}
+
+#[derive(Clone, Copy)]
 #[repr(u8)]
 enum PPUMode {
     HBlank  = 0,
@@ -25,121 +44,135 @@ enum PPUMode {
     Drawing = 3, // VRAM read mode?
 }
 
+/// How lines are drawn
+#[derive(Clone, Copy)]
+pub enum Renderer {
+    /// A whole line at once at the end of mode 3, which always takes 172 dots
+    Scanline,
+    /// Dot by dot through the pixel FIFOs, see `fifo`
+    PixelFifo,
+}
+
 pub struct GPU {
-    pub clock: u16,
     mode: PPUMode,
+    pub renderer: Renderer,
+    /// Length of mode 3 on the current line, while the pixel FIFO is still
+    /// drawing it's how far in the next step is
+    mode3_dots: u16,
+    /// The line the pixel FIFO is drawing in mode 3
+    line: Option<fifo::Line>,
+    /// Set on entering VBlank, cleared once the frame has been drawn
+    pub frame_ready: bool,
+    /// RGB565 pixels, drawn a line at a time
+    pub frame: Vec<u8>,
+    /// Line of the window drawn next, it only counts lines the window was on
+    window_line: u8,
 }
 
 impl GPU {
     pub fn new() -> GPU {
-        GPU { clock: 0, mode: PPUMode::VBlank }
+        GPU {
+            mode: PPUMode::VBlank,
+            renderer: Renderer::Scanline,
+            mode3_dots: 172,
+            line: None,
+            frame_ready: false,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 2],
+            window_line: 0,
+        }
     }
 
-    pub fn step(&mut self, mem: &mut Memory, cycles: u16) -> (bool, bool) {
-        let mut redraw = false;
-        let mut vblank = false;
-        self.clock += cycles;
-
-        match self.mode {
+    /// Move on to the next mode, or the next line in VBlank, returns the
+    /// cycles until the following one. The GPU lives in `mem`, which it
+    /// needs all of to draw.
+    pub fn step(mem: &mut Memory) -> u16 {
+        match mem.gpu.mode {
             PPUMode::HBlank => {
-                // HBlank
-                if self.clock >= 204 {
-                    self.clock = 0;
-                    *mem.reg_ly() = mem.reg_ly().wrapping_add(1);
-
-                    if *mem.reg_ly() == 143 {
-                        self.mode = PPUMode::VBlank;
-                        *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (PPUMode::VBlank as u8);
-                        vblank = true;
-                        redraw = true;
-                    } else {
-                        self.mode = PPUMode::OAMScan;
-                        *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (PPUMode::OAMScan as u8);
-                    }
+                *mem.reg_ly() = mem.reg_ly().wrapping_add(1);
+
+                if *mem.reg_ly() == SCREEN_HEIGHT as u8 {
+                    GPU::set_mode(mem, PPUMode::VBlank);
+                    mem.request_interrupt(Interrupt::VBlank);
+                    mem.gpu.frame_ready = true;
+                    456
+                } else {
+                    GPU::set_mode(mem, PPUMode::OAMScan);
+                    80
                 }
             }
             PPUMode::VBlank => {
-                // VBlank
-                if self.clock >= 456 {
-                    self.clock = 0;
-                    *mem.reg_ly() = mem.reg_ly().wrapping_add(1);
-
-                    if *mem.reg_ly() > 153 {
-                        *mem.reg_ly() = 0;
-                        self.mode = PPUMode::OAMScan;
-                        *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (PPUMode::OAMScan as u8);
-                    }
+                *mem.reg_ly() = mem.reg_ly().wrapping_add(1);
+
+                if *mem.reg_ly() > 153 {
+                    *mem.reg_ly() = 0;
+                    mem.gpu.window_line = 0;
+                    GPU::set_mode(mem, PPUMode::OAMScan);
+                    80
+                } else {
+                    456
                 }
             }
             PPUMode::OAMScan => {
-                // OAM read mode
-                if self.clock >= 80 {
-                    self.clock = 0;
-                    self.mode = PPUMode::Drawing;
-                    *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (PPUMode::Drawing as u8);
+                GPU::set_mode(mem, PPUMode::Drawing);
+                if let Renderer::PixelFifo = mem.gpu.renderer {
+                    let line = fifo::Line::start(mem);
+                    mem.gpu.mode3_dots = line.pixels_left();
+                    mem.gpu.line = Some(line);
+                } else {
+                    mem.gpu.mode3_dots = 172;
                 }
+                mem.gpu.mode3_dots
             }
             PPUMode::Drawing => {
-                // VRAM read mode
-                if self.clock >= 172 {
-                    self.clock = 0;
-                    self.mode = PPUMode::HBlank;
-                    *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (PPUMode::HBlank as u8);
-                    // self.draw_scanline();
+                if let Some(mut line) = mem.gpu.line.take() {
+                    line.run_until(mem, mem.gpu.mode3_dots);
+                    if !line.done() {
+                        // come back once there's been time for the rest of it
+                        let dots = line.pixels_left();
+                        mem.gpu.mode3_dots += dots;
+                        mem.gpu.line = Some(line);
+                        return dots;
+                    }
+                } else {
+                    GPU::draw_scanline(mem);
                 }
+                GPU::set_mode(mem, PPUMode::HBlank);
+                // HBlank gets what's left of the line
+                376 - mem.gpu.mode3_dots
             }
         }
-        (redraw, vblank)
     }
 
-    #[inline]
-    fn set_pixel(buffer: &mut [u8], x: u8, y: u8, color_index: usize) {
-        let sx = x as usize;
-        let sy = y as usize;
-        let offset = ((sy * 256) + sx) * 2;
-        let (top, bottom) = COLORS[color_index];
-        buffer[offset] = bottom;
-        buffer[offset+1] = top;
-    }
-
-    fn draw_tile(&self, mem: &Memory, tile_addr: u16, buffer: &mut [u8], x: u8, y: u8) {
-        let mut mut_tile_addr = tile_addr;
-        for r in 0..8 {
-            let line1 = mem.read8(mut_tile_addr);
-            mut_tile_addr += 1;
-            let line2 = mem.read8(mut_tile_addr);
-            mut_tile_addr += 1;
-            for c in 0..8 {
-                let mut color_index = (line1 >> (7 - c)) & 1;
-                color_index |= if line2 & (0x80 >> c) != 0 { 2 } else { 0 };
-                GPU::set_pixel(buffer, x.wrapping_add(c), y.wrapping_add(r), color_index as usize);
-            }
+    /// Draw up to `dots` before the next step, so what the pixel FIFO reads
+    /// can be changed mid-line
+    pub fn catch_up(mem: &mut Memory, dots: u16) {
+        if let Some(mut line) = mem.gpu.line.take() {
+            line.run_until(mem, mem.gpu.mode3_dots.saturating_sub(dots));
+            mem.gpu.line = Some(line);
         }
     }
 
-    fn draw_sprite(&self, mem: &Memory, src_addr: u16, buffer: &mut [u8], x: u8, y: u8, flags: u8) {
-        let mut mut_src_addr = src_addr;
-        for r in 0..8 {
-            let line1 = mem.read8(mut_src_addr);
-            mut_src_addr += 1;
-            let line2 = mem.read8(mut_src_addr);
-            mut_src_addr += 1;
-            for c in 0..8 {
-                let mut color_index = (line1 >> (7 - c)) & 1;
-                color_index |= if line2 & (0x80 >> c) != 0 { 2 } else { 0 };
-                if color_index > 0 {
-                    let mut gx = x.wrapping_add(c);
-                    let mut gy = y.wrapping_add(r);
-                    if (flags & SPRITE_FLIP_H) != 0 { gx = x.wrapping_add(7-c); }
-                    if (flags & SPRITE_FLIP_V) != 0 { gy = y.wrapping_add(7-r); }
-                    GPU::set_pixel(buffer, gx, gy, color_index as usize);
-                }
-            }
-        }
+    fn set_mode(mem: &mut Memory, mode: PPUMode) {
+        *mem.reg_stat() = (*mem.reg_stat() & 0xfc) | (mode as u8);
+        mem.gpu.mode = mode;
+    }
+
+    #[inline]
+    fn set_pixel(frame: &mut [u8], x: usize, y: usize, shade: u8) {
+        let offset = (y * SCREEN_WIDTH + x) * 2;
+        let (top, bottom) = COLORS[shade as usize];
+        frame[offset] = bottom;
+        frame[offset + 1] = top;
     }
 
-    fn get_tile_addr(&self, mem: &mut Memory, tile_id: u8) -> u16 {
-        if *mem.reg_lcdc() & LCDC_BG_TILE_DATA != 0 {
+    /// Color index of pixel `bit` (0 is the leftmost) in a row of tile data
+    #[inline]
+    pub fn tile_color(low: u8, high: u8, bit: u8) -> u8 {
+        ((low >> (7 - bit)) & 1) | (((high >> (7 - bit)) & 1) << 1)
+    }
+
+    pub fn get_tile_addr(lcdc: u8, tile_id: u8) -> u16 {
+        if lcdc & LCDC_BG_TILE_DATA != 0 {
             (tile_id as u16) * 16 + 0x8000
         } else {
             let tile_sid = (tile_id as i8) as i16 * 16;
@@ -147,79 +180,209 @@ impl GPU {
         }
     }
 
-    pub fn draw_screen(&self, mem: &mut Memory, buffer: &mut [u8]) {
-        let mut tile_id: u8;
-        let mut tile_addr: u16;
-        let mut tile_ptr: u16 = 0x9800;
-
-        if *mem.reg_lcdc() & LCDC_BG_TILE_MAP_SELECT != 0 {
-            tile_ptr = 0x9c00;
-        }
+    /// Color index at (`x`, `y`) of the 256x256 pixel map at `map`
+    fn map_color(mem: &Memory, lcdc: u8, map: u16, x: u8, y: u8) -> u8 {
+        let tile_id = mem.read8(map + (y as u16 / 8) * 32 + x as u16 / 8);
+        let row_addr = GPU::get_tile_addr(lcdc, tile_id) + (y as u16 % 8) * 2;
+        GPU::tile_color(mem.read8(row_addr), mem.read8(row_addr + 1), x % 8)
+    }
 
-        // Tiles
-        for r in 0..32 {
-            for c in 0..32 {
-                tile_id = mem.read8(tile_ptr);
-                tile_ptr += 1;
-                tile_addr = self.get_tile_addr(mem, tile_id);
-                let scx = *mem.reg_scx();
-                let scy = *mem.reg_scy();
-                self.draw_tile(
-                    mem,
-                    tile_addr,
-                    buffer,
-                    ((c * 8u16).wrapping_sub(scx as u16) & 0xff).try_into().unwrap(),
-                    ((r * 8u16).wrapping_sub(scy as u16) & 0xff).try_into().unwrap(),
-                );
+    /// Addresses of the sprites on line `ly` in OAM order, OAM scan picks
+    /// the first 10
+    pub fn line_sprites(mem: &Memory, ly: u8, lcdc: u8) -> ([u16; MAX_SPRITES_PER_LINE], usize) {
+        let height = GPU::sprite_height(lcdc);
+        let mut sprites = [0u16; MAX_SPRITES_PER_LINE];
+        let mut count = 0;
+        for sprite_addr in (0xfe00..0xfea0).step_by(4) {
+            let row = ly as i16 + 16 - mem.read8(sprite_addr) as i16;
+            if (0..height).contains(&row) {
+                sprites[count] = sprite_addr;
+                count += 1;
+                if count == MAX_SPRITES_PER_LINE {
+                    break;
+                }
             }
         }
+        (sprites, count)
+    }
 
-        let mut win_ptr: u16 = 0x9800;
+    fn sprite_height(lcdc: u8) -> i16 {
+        if lcdc & LCDC_SPRITE_DOUBLE_HEIGHT != 0 { 16 } else { 8 }
+    }
 
-        if *mem.reg_lcdc() & LCDC_WINDOW_TILE_MAP_SELECT != 0 {
-            win_ptr = 0x9c00;
+    /// The 8 pixels of a sprite on line `ly`, left to right
+    pub fn sprite_pixels(mem: &Memory, sprite_addr: u16, ly: u8, lcdc: u8) -> [SpritePixel; 8] {
+        let height = GPU::sprite_height(lcdc);
+        let flags = mem.read8(sprite_addr + 3);
+        let mut row = ly as i16 + 16 - mem.read8(sprite_addr) as i16;
+        if flags & SPRITE_FLIP_V != 0 {
+            row = height - 1 - row;
+        }
+        let mut tile_id = mem.read8(sprite_addr + 2) as u16;
+        if height == 16 {
+            tile_id &= 0xfe;
         }
+        let row_addr = 0x8000 + tile_id * 16 + row as u16 * 2;
+        let (low, high) = (mem.read8(row_addr), mem.read8(row_addr + 1));
+        let behind_bg = flags & SPRITE_PRIORITY != 0;
+        let palette = if flags & SPRITE_PALETTE != 0 { 0xff49 } else { 0xff48 };
+        let mut pixels = [SpritePixel { color: 0, behind_bg, palette }; 8];
+        for (bit, pixel) in pixels.iter_mut().enumerate() {
+            let bit = if flags & SPRITE_FLIP_H != 0 { 7 - bit } else { bit };
+            pixel.color = GPU::tile_color(low, high, bit as u8);
+        }
+        pixels
+    }
 
-        // Window
-        if *mem.reg_lcdc() & LCDC_WINDOW_ON != 0 {
-            for r in 0..32 {
-                for c in 0..32 {
-                    tile_id = mem.read8(win_ptr);
-                    win_ptr += 1;
-                    tile_addr = self.get_tile_addr(mem, tile_id);
-                    let x = (c * 8u8).wrapping_add(*mem.reg_wx()).wrapping_sub(7);
-                    let y = (r * 8u8).wrapping_add(*mem.reg_wy());
-                    if tile_id != 0 && x < 167 && y < 144 {
-                        self.draw_tile(mem, tile_addr, buffer, x, y);
-                    }
+    /// Put a BG or window pixel and the sprite pixel over it on screen, each
+    /// through its palette. Priority goes by the color index before that.
+    pub fn mix_pixel(mem: &mut Memory, x: usize, bg: u8, sprite: Option<SpritePixel>) {
+        let (palette, color) = match sprite {
+            Some(sprite) if sprite.behind_bg && bg != 0 => (0xff47, bg),
+            Some(sprite) => (sprite.palette, sprite.color),
+            None => (0xff47, bg),
+        };
+        let shade = if palette == 0xff47 && mem.read8(0xff40) & LCDC_SHOW_BG == 0 {
+            // BG off is white whatever BGP says
+            0
+        } else {
+            (mem.read8(palette) >> (color * 2)) & 0x03
+        };
+        let y = mem.read8(0xff44) as usize;
+        GPU::set_pixel(&mut mem.gpu.frame, x, y, shade);
+    }
+
+    /// Draw line LY with the registers as they are at the end of mode 3, so
+    /// changes between lines show up like on hardware
+    fn draw_scanline(mem: &mut Memory) {
+        let ly = mem.read8(0xff44);
+        let lcdc = mem.read8(0xff40);
+        // BG and window color indices, sprites need them for priority
+        let mut line = [0u8; SCREEN_WIDTH];
+
+        // with BG off a DMG shows neither BG nor window
+        if lcdc & LCDC_SHOW_BG != 0 {
+            let scx = mem.read8(0xff43);
+            let y = ly.wrapping_add(mem.read8(0xff42));
+            let map = GPU::bg_map(lcdc);
+            for (x, color) in line.iter_mut().enumerate() {
+                *color = GPU::map_color(mem, lcdc, map, (x as u8).wrapping_add(scx), y);
+            }
+
+            if GPU::window_visible(mem, lcdc) {
+                let wx = mem.read8(0xff4b) as usize;
+                let map = GPU::window_map(lcdc);
+                let window_line = mem.gpu.window_line;
+                for (x, color) in line.iter_mut().enumerate().skip(wx.saturating_sub(7)) {
+                    *color = GPU::map_color(mem, lcdc, map, (x + 7 - wx) as u8, window_line);
                 }
+                mem.gpu.next_window_line();
             }
         }
 
-        // Sprites
-        let mut id: u16;
-        let mut flags: u8;
-        let mut sprite_addr: u16 = 0xfe00;
-        if *mem.reg_lcdc() & LCDC_SHOW_SPRITES != 0 {
-            for _r in 0..40 {
-                let y = mem.read8(sprite_addr).wrapping_sub(16);
-                sprite_addr += 1;
-                let x = mem.read8(sprite_addr).wrapping_sub(8);
-                sprite_addr += 1;
-                id = mem.read8(sprite_addr) as u16;
-                sprite_addr += 1;
-                flags = mem.read8(sprite_addr);
-                sprite_addr += 1;
-
-                if x == 0 && y == 0 {
-                    continue;
-                }
-                self.draw_sprite(mem, id * 16 + 0x8000, buffer, x, y, flags);
+        let mut sprite_line: [Option<SpritePixel>; SCREEN_WIDTH] = [None; SCREEN_WIDTH];
+        if lcdc & LCDC_SHOW_SPRITES != 0 {
+            let (mut sprites, count) = GPU::line_sprites(mem, ly, lcdc);
+            // on a DMG the leftmost sprite wins, then the first in OAM
+            sprites[..count].sort_by_key(|&sprite_addr| (mem.read8(sprite_addr + 1), sprite_addr));
 
-                if *mem.reg_lcdc() & LCDC_SPRITE_DOUBLE_HEIGHT != 0 {
-                    self.draw_sprite(mem, id * 16 + 0x8000 + 16, buffer, x, y + 8, flags);
+            for &sprite_addr in &sprites[..count] {
+                let left = mem.read8(sprite_addr + 1) as i16 - 8;
+                for (i, &pixel) in GPU::sprite_pixels(mem, sprite_addr, ly, lcdc).iter().enumerate() {
+                    let x = left + i as i16;
+                    if !(0..SCREEN_WIDTH as i16).contains(&x) || sprite_line[x as usize].is_some() {
+                        continue;
+                    }
+                    // color 0 is transparent, a sprite further back can show through
+                    if pixel.color != 0 {
+                        sprite_line[x as usize] = Some(pixel);
+                    }
                 }
             }
         }
+
+        for x in 0..SCREEN_WIDTH {
+            GPU::mix_pixel(mem, x, line[x], sprite_line[x]);
+        }
+    }
+
+    pub fn window_line(&self) -> u8 {
+        self.window_line
+    }
+
+    /// The window only moves on to its next line when it's been drawn
+    pub fn next_window_line(&mut self) {
+        self.window_line += 1;
+    }
+
+    /// Whether the window is enabled and on screen on line LY
+    pub fn window_visible(mem: &Memory, lcdc: u8) -> bool {
+        lcdc & LCDC_WINDOW_ON != 0
+            && mem.read8(0xff44) >= mem.read8(0xff4a)
+            && (mem.read8(0xff4b) as usize) < SCREEN_WIDTH + 7
+    }
+
+    pub fn window_map(lcdc: u8) -> u16 {
+        if lcdc & LCDC_WINDOW_TILE_MAP_SELECT != 0 { 0x9c00 } else { 0x9800 }
+    }
+
+    pub fn bg_map(lcdc: u8) -> u16 {
+        if lcdc & LCDC_BG_TILE_MAP_SELECT != 0 { 0x9c00 } else { 0x9800 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    /// Memory with the PPU about to start line 0
+    fn memory_before_line_0() -> Memory {
+        let mut mem = Memory::with_cartridge(Cartridge::with_code(0x00, 0, &[]));
+        mem.data[0xff44] = 153;
+        mem
+    }
+
+    #[test]
+    fn scanlines_use_the_registers_of_their_line() {
+        let mut mem = memory_before_line_0();
+        // tile 1 is solid color 3, only the top left of the map uses it
+        mem.data[0x8010..0x8020].fill(0xff);
+        mem.data[0x9800] = 0x01;
+        mem.write8(0xff40, 0x91);
+        mem.write8(0xff47, 0xe4);
+        for _ in 0..3 {
+            mem.step_ppu();
+        }
+        mem.write8(0xff43, 0x08);
+        for _ in 0..3 {
+            mem.step_ppu();
+        }
+        let pixel = |x: usize, y: usize| mem.gpu.frame[(y * SCREEN_WIDTH + x) * 2];
+        assert_ne!(pixel(0, 0), pixel(8, 0));
+        assert_eq!(pixel(0, 1), pixel(8, 0));
+    }
+
+    #[test]
+    fn palettes_map_colors_but_sprite_color_0_stays_transparent() {
+        let mut mem = memory_before_line_0();
+        // BG is all color 1, the sprite's left half color 0 and right half color 3
+        mem.data[0x8000..0x8010].copy_from_slice(&[0xff, 0x00].repeat(8));
+        mem.data[0x8010..0x8020].copy_from_slice(&[0x0f, 0x0f].repeat(8));
+        mem.data[0xfe00..0xfe04].copy_from_slice(&[16, 8, 0x01, 0x10]);
+        mem.write8(0xff40, 0x93);
+        mem.write8(0xff47, 0x0c); // color 1 is shade 3
+        mem.write8(0xff48, 0xff);
+        mem.write8(0xff49, 0x40); // color 3 is shade 1, color 0 would be 0
+        for _ in 0..3 {
+            mem.step_ppu();
+        }
+        let pixel = |x: usize| {
+            let offset = x * 2;
+            (mem.gpu.frame[offset + 1], mem.gpu.frame[offset])
+        };
+        assert_eq!(pixel(0), pixel(100));
+        assert_eq!(pixel(0), (0x31, 0x6a));
+        assert_eq!(pixel(7), (0x97, 0x08));
     }
 }
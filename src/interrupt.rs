@@ -0,0 +1,84 @@
+#[derive(Clone, Copy)]
+pub enum Interrupt {
+    VBlank = 0x01,
+    LCDC = 0x02,
+    Timer = 0x04,
+    Serial = 0x08,
+    JoyPad = 0x10,
+}
+
+/// Ordered from highest to lowest priority
+static PRIORITY: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LCDC,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::JoyPad,
+];
+
+impl Interrupt {
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LCDC => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::JoyPad => 0x0060,
+        }
+    }
+
+    /// Highest priority interrupt in an IE & IF mask
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        PRIORITY.iter().copied().find(|&interrupt| pending & interrupt as u8 != 0)
+    }
+}
+
+/// Interrupt master enable (IME) along with the delayed enable of EI. The
+/// request (IF) and enable (IE) registers themselves live in memory at
+/// $ff0f and $ffff.
+pub struct InterruptController {
+    pub ime: bool,
+    /// Instructions left to execute before a pending EI sets IME
+    enable_delay: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController { ime: false, enable_delay: 0 }
+    }
+
+    /// EI, IME is set once the instruction following EI has executed
+    pub fn enable_delayed(&mut self) {
+        if !self.ime && self.enable_delay == 0 {
+            self.enable_delay = 2;
+        }
+    }
+
+    /// RETI, IME is set right away
+    pub fn enable(&mut self) {
+        self.ime = true;
+        self.enable_delay = 0;
+    }
+
+    /// DI, also cancels an EI that hasn't taken effect yet
+    pub fn disable(&mut self) {
+        self.ime = false;
+        self.enable_delay = 0;
+    }
+
+    /// Whether an EI is waiting to set IME
+    #[cfg(test)]
+    pub fn enable_pending(&self) -> bool {
+        self.enable_delay > 0
+    }
+
+    /// Called at the end of every executed instruction
+    pub fn step(&mut self) {
+        if self.enable_delay > 0 {
+            self.enable_delay -= 1;
+            if self.enable_delay == 0 {
+                self.ime = true;
+            }
+        }
+    }
+}
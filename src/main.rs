@@ -1,16 +1,40 @@
 extern crate sdl2;
 
+mod bus;
+mod cartridge;
 mod cpu;
+mod disasm;
+mod fifo;
 mod gb;
 mod gpu;
+mod interrupt;
 mod memory;
+mod opcodes;
+mod scheduler;
+mod timer;
+mod trace;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
 use std::collections::HashSet;
-use sdl2::rect::Rect;
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long a rumble effect lasts if the cartridge never turns the motor off
+const RUMBLE_DURATION_MS: u32 = 1000;
+
+/// Write battery-backed RAM, a failed save shouldn't take the game down with it
+fn write_save(gb: &mut gb::GB) {
+    if let Err(e) = gb.save() {
+        eprintln!("!!! Couldn't write {}: {}", gb.save_path.display(), e);
+    }
+}
 
 fn handle_event(event: &Event, gb: &mut gb::GB) -> bool {
     match event {
@@ -141,41 +165,164 @@ fn find_sdl_gl_driver() -> Option<u32> {
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(short, long, default_value_t = 2)]
     scale_factor: u32,
 
-    #[clap(short, long)]
-    break_points: Option<Vec<String>>,
+    #[clap(short, long, value_parser = parse_address)]
+    break_points: Option<Vec<u16>>,
+
+    /// Advance the PPU and timer on every CPU memory access instead of once per instruction
+    #[clap(long)]
+    mcycle: bool,
+
+    /// Draw through the pixel FIFOs dot by dot, mode 3 takes as long as it does on hardware
+    #[clap(long)]
+    pixel_fifo: bool,
+
+    /// What to do when the CPU runs into an illegal opcode
+    #[clap(long, value_enum, default_value_t = IllegalOpcodePolicy::Lockup)]
+    illegal_opcode: IllegalOpcodePolicy,
+
+    /// Write a gameboy-doctor style instruction trace to this file, - for stdout
+    #[clap(long)]
+    trace: Option<String>,
 
-    #[clap()]
+    /// Only trace instructions in this address range, e.g. 0x4000:0x7fff
+    #[clap(long, value_parser = parse_address_range, requires = "trace")]
+    trace_pc: Option<RangeInclusive<u16>>,
+
+    /// Only trace instructions running from this ROM bank
+    #[clap(long, requires = "trace")]
+    trace_bank: Option<usize>,
+
+    /// Frame at which to start tracing
+    #[clap(long, default_value_t = 0, requires = "trace")]
+    trace_start_frame: u64,
+
+    /// Frame at which to stop tracing
+    #[clap(long, requires = "trace")]
+    trace_stop_frame: Option<u64>,
+
+    /// DMG boot ROM to run before the cartridge instead of the built-in one
+    #[clap(long)]
+    boot_rom: Option<PathBuf>,
+
+    /// Keep .sav files in this directory instead of next to the ROM
+    #[clap(long)]
+    save_dir: Option<PathBuf>,
+
+    /// Seconds between saves of battery-backed RAM that changed, 0 to only save on exit
+    #[clap(long, default_value_t = 10)]
+    autosave_interval: u64,
+
+    #[clap(required = true)]
+    rom_path: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum IllegalOpcodePolicy {
+    /// Keep running with the CPU hung, like the hardware does
+    Lockup,
+    /// Pause with the window still open and print the registers
+    Break,
+    /// Exit with a report of the registers and the whole address space
+    Abort,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Disassemble a ROM into RGBDS syntax
+    Disasm(DisasmArgs),
+    /// Print a ROM's cartridge header
+    Info(InfoArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct InfoArgs {
     rom_path: String,
 }
 
+#[derive(clap::Args, Debug)]
+struct DisasmArgs {
+    rom_path: String,
+
+    /// ROM bank to disassemble, all of them when omitted
+    #[clap(short, long)]
+    bank: Option<usize>,
+
+    /// First address to disassemble within the bank
+    #[clap(long, value_parser = parse_address)]
+    start: Option<u16>,
+
+    /// Last address to disassemble within the bank
+    #[clap(long, value_parser = parse_address)]
+    end: Option<u16>,
+
+    /// Disassemble the function at this address, following its jumps, instead of a range
+    #[clap(short, long, value_parser = parse_address, conflicts_with_all = ["start", "end"])]
+    function: Option<u16>,
+
+    /// Add cycles and affected flags to every line
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+/// Accepts 0x1234, $1234 or decimal
+fn parse_address(address_str: &str) -> Result<u16, String> {
+    let result = if let Some(hex) = address_str.strip_prefix("0x").or_else(|| address_str.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        address_str.parse::<u16>()
+    };
+    result.map_err(|_| format!("Invalid address: {}", address_str))
+}
+
+/// Accepts START:END with both ends as in `parse_address`
+fn parse_address_range(range_str: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range_str
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid address range: {}", range_str))?;
+    Ok(parse_address(start)?..=parse_address(end)?)
+}
+
 fn main() {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Disasm(disasm_args)) => return disasm_command(disasm_args),
+        Some(Command::Info(info_args)) => return info_command(info_args),
+        None => {}
+    }
+
     let scale_factor = args.scale_factor;
-    let rom_path = args.rom_path;
+    let rom_path = args.rom_path.unwrap();
     let mut break_points: HashSet<u16> = args.break_points
         .unwrap_or(vec![])
         .into_iter()
-        .map(|address_str| {
-            if address_str.starts_with("0x") {
-                u16::from_str_radix(&address_str[2..], 16).unwrap_or_else(|_| {
-                    panic!("Invalid breakpoint address: {}", address_str)
-                })
-            } else {
-                address_str.parse::<u16>().unwrap_or_else(|_| {
-                    panic!("Invalid breakpoint address: {}", address_str)
-                })
-            }
-        })
         .collect();
 
     break_points.extend::<Vec<u16>>(vec![
         // for testing
     ]);
 
+    let mut gb = gb::GB::with_rom(&rom_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(boot_rom_path) = &args.boot_rom {
+        let loaded = std::fs::read(boot_rom_path)
+            .map_err(|e| e.to_string())
+            .and_then(|boot_rom| gb.set_boot_rom(boot_rom));
+        if let Err(e) = loaded {
+            eprintln!("{}: {}", boot_rom_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
     let gl_driver = find_sdl_gl_driver().unwrap();
 
     let sdl_context = sdl2::init().unwrap();
@@ -194,15 +341,54 @@ fn main() {
     let texture_creator = canvas.texture_creator();
     let mut texture = texture_creator.create_texture_target(
         PixelFormatEnum::RGB565,
-        256, 256
+        SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32
     ).unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let game_controller_subsystem = sdl_context.game_controller().unwrap();
 
-    let mut gb = gb::GB::with_rom(&rom_path);
+    if let Some(save_dir) = &args.save_dir {
+        gb.set_save_dir(save_dir);
+    }
+    gb.load_save();
     gb.reset();
+    if args.mcycle {
+        gb.cpu.timing = cpu::Timing::MCycle;
+    }
+    if args.pixel_fifo {
+        gb.mem.gpu.renderer = gpu::Renderer::PixelFifo;
+    }
+    // rumble carts shake the first game controller
+    let controller = (0..game_controller_subsystem.num_joysticks().unwrap_or(0))
+        .filter(|&index| game_controller_subsystem.is_game_controller(index))
+        .find_map(|index| game_controller_subsystem.open(index).ok());
+    if let Some(mut controller) = controller {
+        gb.on_rumble = Some(Box::new(move |on| {
+            // the motor is switched on and off rapidly, every change restarts the effect
+            let strength = if on { 0xffff } else { 0 };
+            let _ = controller.set_rumble(strength, strength, RUMBLE_DURATION_MS);
+        }));
+    }
+    if let Some(trace_path) = &args.trace {
+        let out: Box<dyn std::io::Write> = if trace_path == "-" {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            let file = File::create(trace_path)
+                .unwrap_or_else(|e| panic!("Couldn't create {}: {}", trace_path, e));
+            Box::new(BufWriter::new(file))
+        };
+        let mut tracer = trace::Tracer::new(out);
+        tracer.pc_range = args.trace_pc.clone();
+        tracer.bank = args.trace_bank;
+        tracer.start_frame = args.trace_start_frame;
+        tracer.stop_frame = args.trace_stop_frame;
+        gb.cpu.tracer = Some(tracer);
+    }
 
     println!("ROM Title: {:?}", gb.rom_title);
-    let mut frame_buffer: [u8; 256 * 256 * 2] = [0; 256 * 256 * 2];
+    let mut frame_buffer = [0; SCREEN_WIDTH * SCREEN_HEIGHT * 2];
+    let autosave_interval = Duration::from_secs(args.autosave_interval);
+    let mut last_save = Instant::now();
+    let mut paused = false;
     'running: loop {
         for event in event_pump.poll_iter() {
             if handle_event(&event, &mut gb) {
@@ -210,25 +396,160 @@ fn main() {
             }
         }
 
+        if paused {
+            // keep the window and its last frame up without spinning
+            std::thread::sleep(Duration::from_millis(16));
+            continue;
+        }
+
         if break_points.contains(&gb.cpu.pc) {
-            eprintln!("!!! Hit break point at {:04X}", gb.cpu.pc);
+            let instruction = disasm::decode(|address| gb.mem.read8(address), gb.cpu.pc);
+            eprintln!("!!! Hit break point at {:04X}: {}", gb.cpu.pc, instruction);
             break 'running;
         }
 
-        let (_cycles, redraw) = gb.step(&mut frame_buffer);
+        let (_cycles, redraw) = match gb.step(&mut frame_buffer) {
+            Ok(result) => result,
+            Err(fault) => match args.illegal_opcode {
+                IllegalOpcodePolicy::Lockup => {
+                    eprintln!("!!! {}, CPU locked up", fault);
+                    continue;
+                }
+                IllegalOpcodePolicy::Break => {
+                    eprintln!("!!! {}, paused until the window is closed", fault);
+                    dump_debug(&gb);
+                    paused = true;
+                    continue;
+                }
+                IllegalOpcodePolicy::Abort => {
+                    eprintln!("!!! {}", fault);
+                    write_save(&mut gb);
+                    dump_debug(&gb);
+                    dump_all_mem(&gb);
+                    if let Some(tracer) = &mut gb.cpu.tracer {
+                        tracer.flush();
+                    }
+                    std::process::exit(1);
+                }
+            },
+        };
 
         if redraw {
             canvas.clear();
-            texture.update(Rect::new(0, 0, 256, 256), &frame_buffer, 256 * 2).unwrap();
-            canvas.copy(&texture, Rect::new(0, 0, 160, 144), None).unwrap();
+            texture.update(None, &frame_buffer, SCREEN_WIDTH * 2).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
             canvas.present();
+
+            if args.autosave_interval > 0 && last_save.elapsed() >= autosave_interval {
+                if gb.save_pending() {
+                    write_save(&mut gb);
+                }
+                last_save = Instant::now();
+            }
         }
     }
 
+    // don't leave the controller shaking until the effect runs out
+    if gb.rumble() {
+        if let Some(on_rumble) = &mut gb.on_rumble {
+            on_rumble(false);
+        }
+    }
+    write_save(&mut gb);
     dump_debug(&gb);
     dump_mem(&gb, 0xffb0);
 }
 
+fn disasm_command(args: DisasmArgs) {
+    let rom = std::fs::read(&args.rom_path)
+        .unwrap_or_else(|e| panic!("Couldn't read {}: {}", args.rom_path, e));
+    let bank_window = |bank: usize| if bank == 0 { (0x0000, 0x3fff) } else { (0x4000, 0x7fff) };
+    let bank_count = (rom.len() / 0x4000).max(1);
+    if let Some(bank) = args.bank.filter(|&bank| bank >= bank_count) {
+        eprintln!("{} has {} ROM banks, there's no bank {}", args.rom_path, bank_count, bank);
+        std::process::exit(1);
+    }
+
+    if let Some(address) = args.function {
+        let bank = if address < 0x4000 { 0 } else { args.bank.unwrap_or(1) };
+        let (_, end) = bank_window(bank);
+        println!("{}", disasm::section_header(bank, address));
+        for instruction in disasm::disassemble_function(disasm::rom_bank_reader(&rom, bank), address, end) {
+            println!("{}", disasm::format_line(&instruction, bank, args.verbose));
+        }
+        return;
+    }
+
+    let banks = match args.bank {
+        Some(bank) => bank..=bank,
+        None => 0..=bank_count - 1,
+    };
+    for bank in banks {
+        let (window_start, window_end) = bank_window(bank);
+        let start = args.start.unwrap_or(window_start).max(window_start);
+        let end = args.end.unwrap_or(window_end).min(window_end);
+        if start > end {
+            continue;
+        }
+        println!("{}", disasm::section_header(bank, start));
+        for instruction in disasm::disassemble_range(disasm::rom_bank_reader(&rom, bank), start, end) {
+            println!("{}", disasm::format_line(&instruction, bank, args.verbose));
+        }
+        println!();
+    }
+}
+
+fn info_command(args: InfoArgs) {
+    let rom = std::fs::read(&args.rom_path)
+        .unwrap_or_else(|e| panic!("Couldn't read {}: {}", args.rom_path, e));
+    let header = cartridge::CartridgeHeader::parse(&rom).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args.rom_path, e);
+        std::process::exit(1);
+    });
+    let name_or_unknown = |name: Option<&str>| name.unwrap_or("unknown").to_string();
+
+    println!("Title:             {}", header.title);
+    println!("Manufacturer code: {}", header.manufacturer_code.as_deref().unwrap_or("-"));
+    let cgb = match header.cgb_flag {
+        0xc0 => "CGB only",
+        flag if flag & 0x80 != 0 => "CGB enhanced",
+        _ => "no",
+    };
+    println!("CGB:               {} (${:02x})", cgb, header.cgb_flag);
+    println!("SGB:               {}", if header.sgb { "yes" } else { "no" });
+    if header.old_licensee == 0x33 {
+        println!("Licensee:          new {} ({})", header.new_licensee, name_or_unknown(header.licensee_name()));
+    } else {
+        println!("Licensee:          old ${:02x} ({})", header.old_licensee, name_or_unknown(header.licensee_name()));
+    }
+    println!("Cartridge type:    ${:02x} ({})", header.cartridge_type, name_or_unknown(header.cartridge_type_name()));
+    match header.rom_banks() {
+        Some(banks) => println!("ROM size:          ${:02x} ({} KiB, {} banks)", header.rom_size, banks * 16, banks),
+        None => println!("ROM size:          ${:02x} (unknown)", header.rom_size),
+    }
+    match header.ram_bytes() {
+        Some(bytes) => println!("RAM size:          ${:02x} ({} KiB)", header.ram_size, bytes / 1024),
+        None => println!("RAM size:          ${:02x} (unknown)", header.ram_size),
+    }
+    let destination = match header.destination {
+        0x00 => "Japan",
+        0x01 => "overseas",
+        _ => "unknown",
+    };
+    println!("Destination:       ${:02x} ({})", header.destination, destination);
+    println!("Version:           ${:02x}", header.version);
+    if header.header_checksum_ok() {
+        println!("Header checksum:   ${:02x} (ok)", header.header_checksum);
+    } else {
+        println!("Header checksum:   ${:02x} (MISMATCH, computed ${:02x})", header.header_checksum, header.computed_header_checksum());
+    }
+    if header.global_checksum_ok() {
+        println!("Global checksum:   ${:04x} (ok)", header.global_checksum);
+    } else {
+        println!("Global checksum:   ${:04x} (MISMATCH, computed ${:04x})", header.global_checksum, header.computed_global_checksum());
+    }
+}
+
 fn dump_debug(gb: &gb::GB) {
     println!("");
 
@@ -246,8 +567,8 @@ fn dump_debug(gb: &gb::GB) {
     // stack
     println!("Stack");
     for i in 0..16 {
-        let address = ((i * 2) + gb.cpu.sp - 16) as usize;
-        println!("{:04X} | {:02X}{:02X}", address, gb.mem.data[address+1], gb.mem.data[address]);
+        let address = gb.cpu.sp.wrapping_sub(16).wrapping_add(i * 2);
+        println!("{:04X} | {:02X}{:02X}", address, gb.mem.read8(address.wrapping_add(1)), gb.mem.read8(address));
     }
 }
 
@@ -264,4 +585,16 @@ fn dump_mem(gb: &gb::GB, address: u16) {
         }
         println!("");
     }
-}
\ No newline at end of file
+}
+fn dump_all_mem(gb: &gb::GB) {
+    println!();
+
+    println!("Memory");
+    for row_address in (0..0x10000).step_by(16) {
+        print!("0x{:04X} | ", row_address);
+        for c in 0..16 {
+            print!("{:02X} ", gb.mem.read8((row_address + c) as u16));
+        }
+        println!();
+    }
+}
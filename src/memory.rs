@@ -1,106 +1,75 @@
-use memmap::Mmap;
-
-enum ROMSize {
-    BANKS2,
-    // BANKS4,
-    // BANKS8,
-    // BANKS16,
-    // BANKS32,
-    BANKS64,
-    // BANKS128,
-    // BANKS256,
-    // BANKS512,
+use crate::cartridge::Cartridge;
+use crate::gpu::GPU;
+use crate::interrupt::Interrupt;
+use crate::timer::Timer;
+
+/// OAM DMA in progress
+struct DMA {
+    source: u16,
+    /// Scheduler time of the write to $ff46
+    started_at: u64,
+    /// Bytes copied to OAM so far
+    copied: u16,
 }
 
 pub struct Memory {
-    rom: Mmap,
+    pub cartridge: Cartridge,
     pub data: [u8; 65536],
     pub joypad_states: [u8; 2],
-    rom_size: ROMSize,
-    memory_bank: usize,
+    pub gpu: GPU,
+    pub timer: Timer,
+    /// Mapped over the cartridge at $0000-$00ff until a write to $ff50
+    pub boot_rom: Vec<u8>,
+    boot_rom_mapped: bool,
+    dma: Option<DMA>,
+    cgb: bool,
 }
 
 impl Memory {
-    pub fn with_rom(rom: Mmap) -> Memory {
-        let rom_size: ROMSize = match rom[0x0148] {
-            0 => ROMSize::BANKS2,
-            5 => ROMSize::BANKS64,
-            n => panic!("Unhandled ROM size {}", n),
-        };
+    pub fn with_cartridge(cartridge: Cartridge) -> Memory {
+        let cgb = cartridge.header.cgb_flag & 0x80 != 0;
         Memory {
-            rom,
+            cartridge,
             data: [0; 65536],
             joypad_states: [0, 0],
-            rom_size,
-            memory_bank: 1,
+            gpu: GPU::new(),
+            timer: Timer::new(),
+            boot_rom: vec![],
+            boot_rom_mapped: false,
+            dma: None,
+            cgb,
         }
     }
 
     pub fn read8(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => self.rom[address as usize],
-            0x4000..=0x7fff => {
-                // TODO switchable ROM bank
-                let adjusted_address = 0x4000 * (self.memory_bank - 1) + (address as usize);
-                self.rom[adjusted_address]
-            },
+            0x0000..=0x00ff if self.boot_rom_mapped => self.boot_rom[address as usize],
+            0x0000..=0x7fff => self.cartridge.read_rom(address),
+            0xa000..=0xbfff => self.cartridge.read_ram(address),
             0xe000..=0xfdff => {
-                self.data[(address - 0x1000) as usize]
+                // echo of $c000-$ddff
+                self.data[(address - 0x2000) as usize]
             },
+            0xfea0..=0xfeff => self.read_prohibited(address),
+            0xff04..=0xff07 => self.timer.read(address),
             0xff41 => {
-                // TODO
-                let ly = self.data[0xff44];
-                let lyc = self.data[0xff45];
-                0x80 | if ly == lyc { 2 } else { 0 }
+                let coincidence = if self.data[0xff44] == self.data[0xff45] { 0x04 } else { 0 };
+                0x80 | (self.data[0xff41] & 0x7b) | coincidence
+            }
+            0xff00..=0xff7f => {
+                let mask = io_mask(address, self.cgb);
+                (self.data[address as usize] & mask.read) | !mask.read
             }
             _ => self.data[address as usize]
         }
     }
 
-    pub fn read16(&self, addr: u16) -> u16 {
-        let top: u16 = self.read8(addr) as u16;
-        let bottom: u16 = (self.read8(addr + 1) as u16) << 8;
-        bottom | top
-    }
-
     pub fn write8(&mut self, addr: u16, val: u8) {
         // WIP
         match addr {
-            0x0000..=0x1fff => {
-                if val < 2 {
-                    return;
-                }
-                // TODO: ??? enable RAM bank?
-                panic!("Unhandled write to 0x0000..=0x1fff, val = {:}", val);
-            },
-            0x2000..=0x3fff => {
-                // TODO: implement all ROM sizes
-                match self.rom_size {
-                    ROMSize::BANKS2 => {
-                        // noop
-                    },
-                    ROMSize::BANKS64 => {
-                        match addr {
-                            0x2000..=0x2fff => {
-                                self.memory_bank &= !0xff;
-                                self.memory_bank |= val as usize;
-                            },
-                            0x3000..=0x3fff => {
-                                self.memory_bank &= !0x100;
-                                self.memory_bank |= ((val & 0x1) as usize) << 8;
-                            },
-                            _ => {},
-                        }
-                    },
-                }
-            },
-            0x4000..=0x5fff => {
-                // TODO: ???
-            },
-            0x6000..=0x7fff => {
-                // TODO: actually switch memory mode
-                // memory mode select
-                // MEMORY_MODE = val & 1;
+            0x0000..=0x7fff => {
+                // cartridge mapper registers
+                self.cartridge.write_register(addr, val);
             },
             0x8000..=0x9fff => {
                 // video RAM
@@ -108,17 +77,22 @@ impl Memory {
             },
             0xa000..=0xbfff => {
                 // switchable RAM bank
+                self.cartridge.write_ram(addr, val);
             },
             0xc000..=0xdfff => {
                 // low RAM
                 self.data[addr as usize] = val;
             },
-            0xfe00..=0xfebf => {
+            0xe000..=0xfdff => {
+                // echo RAM
+                self.data[(addr - 0x2000) as usize] = val;
+            },
+            0xfe00..=0xfe9f => {
                 // OAM
                 self.data[addr as usize] = val;
             },
             0xfea0..=0xfeff => {
-                // empty ???
+                // prohibited, writes go nowhere
             },
             0xff00 => {
                 // joypad
@@ -130,31 +104,36 @@ impl Memory {
                     *self.reg_joypad() = 0xe0 | self.joypad_states[1];
                 }
             },
-            0xff04 => {
-                // interrupt register
-                self.data[addr as usize] = val;
-            },
-            0xff0f => {
-                // divider register
-                *self.reg_div() = 0;
+            0xff04..=0xff07 => {
+                // timer
+                self.timer.write(addr, val);
             },
             0xff40 => {
                 // lcdc
                 *self.reg_lcdc() = val;
                 // ???
             },
-            0xff41 => {
-                // lcdc stat
-                *self.reg_stat() = val;
-            },
             0xff46 => {
-                // dma
+                // dma, the transfer itself is started by whoever knows the time
                 *self.reg_dma() = val;
-                self.mem_dma((val as u16) << 8);
+            },
+            0xff4d => {
+                // KEY1, only the "prepare speed switch" bit is writable
+                if self.cgb {
+                    *self.reg_key1() = (*self.reg_key1() & 0x80) | (val & 0x01);
+                }
+            },
+            0xff50 => {
+                // boot ROM disable, it can't be mapped again
+                if val != 0 {
+                    self.boot_rom_mapped = false;
+                }
             },
             0xff00..=0xff7f => {
-                // IO ports + empty
-                self.data[addr as usize] = val;
+                // IO ports, only the writable bits change
+                let mask = io_mask(addr, self.cgb);
+                let reg = &mut self.data[addr as usize];
+                *reg = (*reg & !mask.write) | (val & mask.write);
             },
             0xff80..=0xfffe => {
                 // internal RAM
@@ -164,59 +143,139 @@ impl Memory {
                 // interrupt enable register
                 self.data[addr as usize] = val;
             },
-            _ => {
-                panic!("Unhandled memory write to address: 0x{:04X}", addr);
-            }
         }
     }
 
-    pub fn write16(&mut self, addr: u16, val: u16) {
-        self.write8(addr, (val & 0xff) as u8);
-        self.write8(addr + 1, (val >> 8) as u8);
+    /// $fea0-$feff. A DMG reads $00, or $ff while the PPU has OAM locked.
+    /// CGB revision E repeats the upper nibble of the low address byte,
+    /// earlier revisions differ but nothing relies on them.
+    fn read_prohibited(&self, address: u16) -> u8 {
+        if self.cgb {
+            let nibble = address as u8 & 0xf0;
+            nibble | (nibble >> 4)
+        } else if self.data[0xff41] & 0x03 >= 2 {
+            0xff
+        } else {
+            0x00
+        }
     }
 
-    pub fn mem_dma(&mut self, addr: u16) {
-        let start = addr as usize;
-        let end = start + 160;
-        self.data.copy_within(start..end, 0xfe00) // TODO use copy_nonoverlapping?
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.data[0xff0f] &= !(interrupt as u8);
     }
 
-    // Memory Mapped IO
+    /// Low nibble of P1 as seen by the CPU, a bit is 0 while a selected button is held
+    pub fn joypad_lines(&self) -> u8 {
+        let select = self.data[0xff00];
+        let mut lines = 0x0f;
+        if select & 0x10 == 0 {
+            lines &= self.joypad_states[1];
+        }
+        if select & 0x20 == 0 {
+            lines &= self.joypad_states[0];
+        }
+        lines
+    }
 
-    pub fn reg_joypad(&mut self) -> &mut u8 {
-        &mut self.data[0xff00]
+    pub fn map_boot_rom(&mut self) {
+        self.boot_rom_mapped = true;
     }
 
-    pub fn reg_div(&mut self) -> &mut u8 {
-        &mut self.data[0xff04]
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
     }
 
-    pub fn reg_tima(&mut self) -> &mut u8 {
-        &mut self.data[0xff05]
+    /// ROM bank currently mapped at $4000-$7fff
+    pub fn rom_bank(&self) -> usize {
+        self.cartridge.rom_bank()
     }
 
-    pub fn reg_tma(&mut self) -> &mut u8 {
-        &mut self.data[0xff06]
+    /// Run the PPU's next mode change, returns the CPU cycles until the one after
+    pub fn step_ppu(&mut self) -> u16 {
+        let cycles = GPU::step(self);
+        // the PPU runs at the same speed in CGB double speed mode
+        if self.double_speed() { cycles * 2 } else { cycles }
     }
 
-    pub fn reg_tac(&mut self) -> &mut u8 {
-        &mut self.data[0xff07]
+    /// Catch the PPU up to `cycles` before its next mode change
+    pub fn sync_ppu(&mut self, cycles: u64) {
+        let dots = if self.double_speed() { cycles / 2 } else { cycles };
+        GPU::catch_up(self, dots.min(u16::MAX as u64) as u16);
     }
 
-    pub fn reg_lcdc(&mut self) -> &mut u8 {
-        &mut self.data[0xff40]
+    /// Shift in the byte from the other side, there's never anyone connected
+    pub fn finish_serial_transfer(&mut self) {
+        self.data[0xff01] = 0xff;
+        self.data[0xff02] &= 0x7f;
+        self.request_interrupt(Interrupt::Serial);
     }
 
-    pub fn reg_stat(&mut self) -> &mut u8 {
-        &mut self.data[0xff41]
+    /// Start copying 160 bytes from `value` << 8 to OAM, one per M-cycle
+    /// after a one M-cycle delay. Restarting cancels the running transfer.
+    pub fn start_dma(&mut self, value: u8, now: u64) {
+        // $e000 and up is echo RAM, $fe00 and $ff00 end up in WRAM as well
+        let source = match (value as u16) << 8 {
+            source @ 0xe000..=0xffff => source - 0x2000,
+            source => source,
+        };
+        self.dma = Some(DMA { source, started_at: now, copied: 0 });
+    }
+
+    /// Copy the DMA bytes whose M-cycle has passed by `now`
+    pub fn sync_dma(&mut self, now: u64) {
+        let dma = match &self.dma {
+            Some(dma) => dma,
+            None => return,
+        };
+        let due = (now.saturating_sub(dma.started_at) / 4).saturating_sub(1).min(160) as u16;
+        let (source, copied) = (dma.source, dma.copied);
+        for i in copied..due {
+            self.data[0xfe00 + i as usize] = self.read8(source + i);
+        }
+        if due == 160 {
+            self.dma = None;
+        } else if let Some(dma) = &mut self.dma {
+            dma.copied = due;
+        }
+    }
+
+    /// While DMA runs the CPU can only reach HRAM
+    pub fn dma_blocks(&self, address: u16, now: u64) -> bool {
+        match &self.dma {
+            Some(dma) => !(0xff80..=0xfffe).contains(&address) && now >= dma.started_at + 4,
+            None => false,
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.data[0xff0f] |= interrupt as u8;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.data[0xff4d] & 0x80 != 0
+    }
+
+    pub fn speed_switch_armed(&self) -> bool {
+        self.data[0xff4d] & 0x01 != 0
+    }
+
+    /// Toggle CGB double speed mode, called by STOP when KEY1 has been armed
+    pub fn switch_speed(&mut self) {
+        *self.reg_key1() = (*self.reg_key1() ^ 0x80) & 0x80;
     }
 
-    pub fn reg_scy(&mut self) -> &mut u8 {
-        &mut self.data[0xff42]
+    // Memory Mapped IO
+
+    pub fn reg_joypad(&mut self) -> &mut u8 {
+        &mut self.data[0xff00]
     }
 
-    pub fn reg_scx(&mut self) -> &mut u8 {
-        &mut self.data[0xff43]
+    pub fn reg_lcdc(&mut self) -> &mut u8 {
+        &mut self.data[0xff40]
+    }
+
+    pub fn reg_stat(&mut self) -> &mut u8 {
+        &mut self.data[0xff41]
     }
 
     pub fn reg_ly(&mut self) -> &mut u8 {
@@ -228,89 +287,120 @@ impl Memory {
     // }
 
     pub fn reg_dma(&mut self) -> &mut u8 {
-        &mut self.data[0xff47]
+        &mut self.data[0xff46]
     }
 
-    pub fn reg_wy(&mut self) -> &mut u8 {
-        &mut self.data[0xff4a]
+    pub fn reg_key1(&mut self) -> &mut u8 {
+        &mut self.data[0xff4d]
     }
+}
 
-    pub fn reg_wx(&mut self) -> &mut u8 {
-        &mut self.data[0xff4b]
-    }
+/// Which bits of an IO register the CPU can read and write. Bits that
+/// can't be read come back as 1, a register that isn't there reads $ff
+/// and ignores writes.
+#[derive(Clone, Copy)]
+struct IOMask {
+    read: u8,
+    write: u8,
 }
 
-/// memory starting at start at $fe00
-#[repr(C)]
-struct IORegisters {
-    /// $fe00
-    pub sprites: [u8; 160],
-    /// Nintendo says use of this area is prohibited
-    _prohibited: [u8; 96],
-
-    // io registers
-    /// $ff00
-    pub joypad: u8,
-    /// $ff01
-    pub serial_transfer: [u8; 2],
-    _padding0: u8,
-    /// $ff04
-    pub timer_divider: [u8; 4],
-    _padding1: [u8; 7],
-    /// $ff0f
-    pub interrupts: u8,
-    /// $ff10
-    pub audio: [u8; 22],
-    _padding2: [u8; 10],
-    /// $ff30
-    pub wave_pattern: [u8; 16],
-    /// $ff40
-    pub lcd_control: u8,
-    /// $ff41
-    pub lcd_stat: u8,
-    /// $ff42
-    pub lcd_scx: u8,
-    /// $ff43
-    pub lcd_scy: u8,
-    /// $ff44: current scan line -- READ-ONLY
-    pub lcd_y: u8,
-    /// $ff45
-    pub lcd_yc: u8,
-    /// $ff46
-    pub oam_dma_source_address: u8,
-    /// $ff47
-    pub bg_palette_data: u8,
-    /// $ff48
-    pub object_palette_0: u8,
-    /// $ff49
-    pub object_palette_1: u8,
-    /// $ff4a
-    pub wx: u8,
-    /// $ff4b
-    pub wy: u8,
-    _padding3: [u8; 3],
-    /// $ff4f
-    pub vram_bank_select: u8,
-    /// $ff50
-    pub bootrom_disable: u8,
-    /// $ff51
-    pub vram_dma: [u8; 5],
-    _padding4: [u8; 18],
-    /// $ff68
-    pub bg_object_pallets: [u8; 4],
-    _padding5: [u8; 4],
-    /// $ff70
-    pub wram_bank_select: u8,
-    _padding6: [u8; 15],
-
-    /// high ram $ff80
-    pub hram: [u8; 0x7f],
-    /// $ffff
-    pub interrupt_enable: u8,
+const UNMAPPED: IOMask = IOMask { read: 0x00, write: 0x00 };
+
+const fn mask(read: u8, write: u8) -> IOMask {
+    IOMask { read, write }
 }
 
-impl IORegisters {
-    fn new() -> IORegisters {
-        unsafe { std::mem::zeroed() }
+/// Masks for $ff00-$ff7f. Registers with side effects (P1, the timer, DMA,
+/// $ff50) are handled in `write8` but still read through here.
+fn io_mask(address: u16, cgb: bool) -> IOMask {
+    match address {
+        0xff00 => mask(0x3f, 0x30), // P1
+        0xff01 => mask(0xff, 0xff), // SB
+        0xff02 if cgb => mask(0x83, 0x83), // SC, bit 1 selects the fast clock
+        0xff02 => mask(0x81, 0x81),
+        0xff04..=0xff07 => mask(0xff, 0xff), // DIV, TIMA, TMA, TAC, masked by the timer
+        0xff0f => mask(0x1f, 0x1f), // IF
+
+        // sound, lengths and frequencies are write-only
+        0xff10 => mask(0x7f, 0x7f), // NR10
+        0xff11 | 0xff16 => mask(0xc0, 0xff), // NR11, NR21
+        0xff12 | 0xff17 => mask(0xff, 0xff), // NR12, NR22
+        0xff13 | 0xff18 | 0xff1d => mask(0x00, 0xff), // NR13, NR23, NR33
+        0xff14 | 0xff19 | 0xff1e => mask(0x40, 0xc7), // NR14, NR24, NR34
+        0xff1a => mask(0x80, 0x80), // NR30
+        0xff1b => mask(0x00, 0xff), // NR31
+        0xff1c => mask(0x60, 0x60), // NR32
+        0xff20 => mask(0x00, 0x3f), // NR41
+        0xff21 | 0xff22 => mask(0xff, 0xff), // NR42, NR43
+        0xff23 => mask(0x40, 0xc0), // NR44
+        0xff24 | 0xff25 => mask(0xff, 0xff), // NR50, NR51
+        0xff26 => mask(0x8f, 0x80), // NR52, the channel bits are read-only
+        0xff30..=0xff3f => mask(0xff, 0xff), // wave RAM
+
+        // LCD
+        0xff40 => mask(0xff, 0xff), // LCDC
+        0xff41 => mask(0x7f, 0x78), // STAT, mode and coincidence are read-only
+        0xff42 | 0xff43 => mask(0xff, 0xff), // SCY, SCX
+        0xff44 => mask(0xff, 0x00), // LY
+        0xff45..=0xff4b => mask(0xff, 0xff), // LYC, DMA, BGP, OBP0, OBP1, WY, WX
+
+        // CGB only
+        0xff4d if cgb => mask(0x81, 0x01), // KEY1
+        0xff4f if cgb => mask(0x01, 0x01), // VBK
+        0xff51..=0xff54 if cgb => mask(0x00, 0xff), // HDMA1-4
+        0xff55 if cgb => mask(0xff, 0xff), // HDMA5
+        0xff56 if cgb => mask(0xc3, 0xc1), // RP
+        0xff68 | 0xff6a if cgb => mask(0xbf, 0xbf), // BCPS, OCPS
+        0xff69 | 0xff6b if cgb => mask(0xff, 0xff), // BCPD, OCPD
+        0xff6c if cgb => mask(0x01, 0x01), // OPRI
+        0xff70 if cgb => mask(0x07, 0x07), // SVBK
+        0xff72 | 0xff73 if cgb => mask(0xff, 0xff),
+        0xff75 if cgb => mask(0x70, 0x70),
+        0xff76 | 0xff77 if cgb => mask(0xff, 0x00), // PCM12, PCM34
+
+        // $ff50 can only be written, everything else isn't connected
+        _ => UNMAPPED,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_with_rom(code: &[u8]) -> Memory {
+        Memory::with_cartridge(Cartridge::with_code(0x00, 0, code))
+    }
+
+    #[test]
+    fn oam_dma_copies_from_rom_a_byte_per_mcycle() {
+        let code: Vec<u8> = (1..=0x30).collect();
+        let mut mem = memory_with_rom(&code);
+        mem.start_dma(0x01, 0);
+        assert!(!mem.dma_blocks(0xc000, 0));
+        assert!(mem.dma_blocks(0xc000, 4));
+        assert!(!mem.dma_blocks(0xff80, 4));
+        assert!(mem.dma_blocks(0xff44, 4));
+        assert!(mem.dma_blocks(0xffff, 4));
+        mem.sync_dma(4 + 0x20 * 4);
+        assert_eq!(mem.data[0xfe00 + 0x1f], 0x20);
+        assert_eq!(mem.data[0xfe00 + 0x20], 0);
+        mem.sync_dma(4 + 0xa0 * 4 + 4);
+        assert_eq!(mem.data[0xfe00..0xfe30], code[..]);
+        assert!(!mem.dma_blocks(0xc000, 4 + 0xa0 * 4 + 4));
+    }
+
+    #[test]
+    fn echo_ram_and_io_masks() {
+        let mut mem = memory_with_rom(&[]);
+        mem.write8(0xe123, 0x42);
+        assert_eq!(mem.read8(0xc123), 0x42);
+        mem.write8(0xfea0, 0x42);
+        assert_eq!(mem.read8(0xfea0), 0x00);
+        mem.write8(0xff26, 0x00);
+        assert_eq!(mem.read8(0xff26), 0x70);
+        mem.write8(0xff41, 0xff);
+        assert_eq!(mem.read8(0xff41) & 0x7b, 0x78);
+        assert_eq!(mem.read8(0xff03), 0xff);
+        assert_eq!(mem.read8(0xff4d), 0xff);
+    }
+}
@@ -0,0 +1,644 @@
+//! Static metadata for every SM83 opcode, shared by the executor and the
+//! debugging tools (disassembler, tracer).
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operand {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    /// (bc)
+    IndBC,
+    /// (de)
+    IndDE,
+    /// (hl)
+    IndHL,
+    /// (hl+), hl incremented after the access
+    IndHLI,
+    /// (hl-), hl decremented after the access
+    IndHLD,
+    /// ($ff00+c)
+    IndC,
+    /// n8
+    Imm8,
+    /// n16
+    Imm16,
+    /// ($ff00+n8)
+    IndImm8,
+    /// (n16)
+    IndImm16,
+    /// e8, signed offset from the address of the next instruction
+    Rel8,
+    /// e8, signed immediate added to sp
+    SImm8,
+    /// sp+e8
+    SPImm8,
+    CondNZ,
+    CondZ,
+    CondNC,
+    CondC,
+    /// rst target
+    Vector(u8),
+    /// bit number of bit/res/set
+    Bit(u8),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlagEffect {
+    Unaffected,
+    Reset,
+    Set,
+    Affected,
+}
+
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    /// Instruction length in bytes, including the opcode (and the $cb prefix)
+    pub length: u8,
+    /// Cycles taken, or cycles when a conditional branch isn't taken
+    pub cycles: u8,
+    /// Cycles when a conditional branch is taken
+    pub cycles_taken: u8,
+    /// Effect on the Z, N, H and C flags, in that order
+    pub flags: [FlagEffect; 4],
+}
+
+impl OpInfo {
+    pub fn cycles(&self, taken: bool) -> u16 {
+        if taken { self.cycles_taken as u16 } else { self.cycles as u16 }
+    }
+
+    pub fn is_illegal(&self) -> bool {
+        self.mnemonic == ILLEGAL.mnemonic
+    }
+}
+
+/// Metadata for a base opcode
+pub fn lookup(op: u8) -> &'static OpInfo {
+    &OPCODES[op as usize]
+}
+
+/// Metadata for the opcode following a $cb prefix
+pub fn lookup_cb(op: u8) -> &'static OpInfo {
+    &CB_OPCODES[op as usize]
+}
+
+const fn flag(effect: u8) -> FlagEffect {
+    match effect {
+        b'-' => FlagEffect::Unaffected,
+        b'0' => FlagEffect::Reset,
+        b'1' => FlagEffect::Set,
+        _ => FlagEffect::Affected,
+    }
+}
+
+/// `flags` is written as in most opcode tables, e.g. "Z0H-"
+const fn op(mnemonic: &'static str, operands: &'static [Operand], length: u8, cycles: u8, flags: &str) -> OpInfo {
+    let flags = flags.as_bytes();
+    OpInfo {
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        cycles_taken: cycles,
+        flags: [flag(flags[0]), flag(flags[1]), flag(flags[2]), flag(flags[3])],
+    }
+}
+
+const fn branch(mnemonic: &'static str, operands: &'static [Operand], length: u8, cycles: u8, cycles_taken: u8) -> OpInfo {
+    let mut info = op(mnemonic, operands, length, cycles, "----");
+    info.cycles_taken = cycles_taken;
+    info
+}
+
+/// Unused opcodes, they lock up the CPU. Also stands in for data bytes.
+pub const ILLEGAL: OpInfo = op("illegal", &[], 1, 4, "----");
+
+use Operand::*;
+
+static OPCODES: [OpInfo; 256] = [
+    op("nop", &[], 1, 4, "----"), // 0x00
+    op("ld", &[BC, Imm16], 3, 12, "----"), // 0x01
+    op("ld", &[IndBC, A], 1, 8, "----"), // 0x02
+    op("inc", &[BC], 1, 8, "----"), // 0x03
+    op("inc", &[B], 1, 4, "Z0H-"), // 0x04
+    op("dec", &[B], 1, 4, "Z1H-"), // 0x05
+    op("ld", &[B, Imm8], 2, 8, "----"), // 0x06
+    op("rlca", &[], 1, 4, "000C"), // 0x07
+    op("ld", &[IndImm16, SP], 3, 20, "----"), // 0x08
+    op("add", &[HL, BC], 1, 8, "-0HC"), // 0x09
+    op("ld", &[A, IndBC], 1, 8, "----"), // 0x0a
+    op("dec", &[BC], 1, 8, "----"), // 0x0b
+    op("inc", &[C], 1, 4, "Z0H-"), // 0x0c
+    op("dec", &[C], 1, 4, "Z1H-"), // 0x0d
+    op("ld", &[C, Imm8], 2, 8, "----"), // 0x0e
+    op("rrca", &[], 1, 4, "000C"), // 0x0f
+    op("stop", &[], 2, 4, "----"), // 0x10
+    op("ld", &[DE, Imm16], 3, 12, "----"), // 0x11
+    op("ld", &[IndDE, A], 1, 8, "----"), // 0x12
+    op("inc", &[DE], 1, 8, "----"), // 0x13
+    op("inc", &[D], 1, 4, "Z0H-"), // 0x14
+    op("dec", &[D], 1, 4, "Z1H-"), // 0x15
+    op("ld", &[D, Imm8], 2, 8, "----"), // 0x16
+    op("rla", &[], 1, 4, "000C"), // 0x17
+    op("jr", &[Rel8], 2, 12, "----"), // 0x18
+    op("add", &[HL, DE], 1, 8, "-0HC"), // 0x19
+    op("ld", &[A, IndDE], 1, 8, "----"), // 0x1a
+    op("dec", &[DE], 1, 8, "----"), // 0x1b
+    op("inc", &[E], 1, 4, "Z0H-"), // 0x1c
+    op("dec", &[E], 1, 4, "Z1H-"), // 0x1d
+    op("ld", &[E, Imm8], 2, 8, "----"), // 0x1e
+    op("rra", &[], 1, 4, "000C"), // 0x1f
+    branch("jr", &[CondNZ, Rel8], 2, 8, 12), // 0x20
+    op("ld", &[HL, Imm16], 3, 12, "----"), // 0x21
+    op("ld", &[IndHLI, A], 1, 8, "----"), // 0x22
+    op("inc", &[HL], 1, 8, "----"), // 0x23
+    op("inc", &[H], 1, 4, "Z0H-"), // 0x24
+    op("dec", &[H], 1, 4, "Z1H-"), // 0x25
+    op("ld", &[H, Imm8], 2, 8, "----"), // 0x26
+    op("daa", &[], 1, 4, "Z-0C"), // 0x27
+    branch("jr", &[CondZ, Rel8], 2, 8, 12), // 0x28
+    op("add", &[HL, HL], 1, 8, "-0HC"), // 0x29
+    op("ld", &[A, IndHLI], 1, 8, "----"), // 0x2a
+    op("dec", &[HL], 1, 8, "----"), // 0x2b
+    op("inc", &[L], 1, 4, "Z0H-"), // 0x2c
+    op("dec", &[L], 1, 4, "Z1H-"), // 0x2d
+    op("ld", &[L, Imm8], 2, 8, "----"), // 0x2e
+    op("cpl", &[], 1, 4, "-11-"), // 0x2f
+    branch("jr", &[CondNC, Rel8], 2, 8, 12), // 0x30
+    op("ld", &[SP, Imm16], 3, 12, "----"), // 0x31
+    op("ld", &[IndHLD, A], 1, 8, "----"), // 0x32
+    op("inc", &[SP], 1, 8, "----"), // 0x33
+    op("inc", &[IndHL], 1, 12, "Z0H-"), // 0x34
+    op("dec", &[IndHL], 1, 12, "Z1H-"), // 0x35
+    op("ld", &[IndHL, Imm8], 2, 12, "----"), // 0x36
+    op("scf", &[], 1, 4, "-001"), // 0x37
+    branch("jr", &[CondC, Rel8], 2, 8, 12), // 0x38
+    op("add", &[HL, SP], 1, 8, "-0HC"), // 0x39
+    op("ld", &[A, IndHLD], 1, 8, "----"), // 0x3a
+    op("dec", &[SP], 1, 8, "----"), // 0x3b
+    op("inc", &[A], 1, 4, "Z0H-"), // 0x3c
+    op("dec", &[A], 1, 4, "Z1H-"), // 0x3d
+    op("ld", &[A, Imm8], 2, 8, "----"), // 0x3e
+    op("ccf", &[], 1, 4, "-00C"), // 0x3f
+    op("ld", &[B, B], 1, 4, "----"), // 0x40
+    op("ld", &[B, C], 1, 4, "----"), // 0x41
+    op("ld", &[B, D], 1, 4, "----"), // 0x42
+    op("ld", &[B, E], 1, 4, "----"), // 0x43
+    op("ld", &[B, H], 1, 4, "----"), // 0x44
+    op("ld", &[B, L], 1, 4, "----"), // 0x45
+    op("ld", &[B, IndHL], 1, 8, "----"), // 0x46
+    op("ld", &[B, A], 1, 4, "----"), // 0x47
+    op("ld", &[C, B], 1, 4, "----"), // 0x48
+    op("ld", &[C, C], 1, 4, "----"), // 0x49
+    op("ld", &[C, D], 1, 4, "----"), // 0x4a
+    op("ld", &[C, E], 1, 4, "----"), // 0x4b
+    op("ld", &[C, H], 1, 4, "----"), // 0x4c
+    op("ld", &[C, L], 1, 4, "----"), // 0x4d
+    op("ld", &[C, IndHL], 1, 8, "----"), // 0x4e
+    op("ld", &[C, A], 1, 4, "----"), // 0x4f
+    op("ld", &[D, B], 1, 4, "----"), // 0x50
+    op("ld", &[D, C], 1, 4, "----"), // 0x51
+    op("ld", &[D, D], 1, 4, "----"), // 0x52
+    op("ld", &[D, E], 1, 4, "----"), // 0x53
+    op("ld", &[D, H], 1, 4, "----"), // 0x54
+    op("ld", &[D, L], 1, 4, "----"), // 0x55
+    op("ld", &[D, IndHL], 1, 8, "----"), // 0x56
+    op("ld", &[D, A], 1, 4, "----"), // 0x57
+    op("ld", &[E, B], 1, 4, "----"), // 0x58
+    op("ld", &[E, C], 1, 4, "----"), // 0x59
+    op("ld", &[E, D], 1, 4, "----"), // 0x5a
+    op("ld", &[E, E], 1, 4, "----"), // 0x5b
+    op("ld", &[E, H], 1, 4, "----"), // 0x5c
+    op("ld", &[E, L], 1, 4, "----"), // 0x5d
+    op("ld", &[E, IndHL], 1, 8, "----"), // 0x5e
+    op("ld", &[E, A], 1, 4, "----"), // 0x5f
+    op("ld", &[H, B], 1, 4, "----"), // 0x60
+    op("ld", &[H, C], 1, 4, "----"), // 0x61
+    op("ld", &[H, D], 1, 4, "----"), // 0x62
+    op("ld", &[H, E], 1, 4, "----"), // 0x63
+    op("ld", &[H, H], 1, 4, "----"), // 0x64
+    op("ld", &[H, L], 1, 4, "----"), // 0x65
+    op("ld", &[H, IndHL], 1, 8, "----"), // 0x66
+    op("ld", &[H, A], 1, 4, "----"), // 0x67
+    op("ld", &[L, B], 1, 4, "----"), // 0x68
+    op("ld", &[L, C], 1, 4, "----"), // 0x69
+    op("ld", &[L, D], 1, 4, "----"), // 0x6a
+    op("ld", &[L, E], 1, 4, "----"), // 0x6b
+    op("ld", &[L, H], 1, 4, "----"), // 0x6c
+    op("ld", &[L, L], 1, 4, "----"), // 0x6d
+    op("ld", &[L, IndHL], 1, 8, "----"), // 0x6e
+    op("ld", &[L, A], 1, 4, "----"), // 0x6f
+    op("ld", &[IndHL, B], 1, 8, "----"), // 0x70
+    op("ld", &[IndHL, C], 1, 8, "----"), // 0x71
+    op("ld", &[IndHL, D], 1, 8, "----"), // 0x72
+    op("ld", &[IndHL, E], 1, 8, "----"), // 0x73
+    op("ld", &[IndHL, H], 1, 8, "----"), // 0x74
+    op("ld", &[IndHL, L], 1, 8, "----"), // 0x75
+    op("halt", &[], 1, 4, "----"), // 0x76
+    op("ld", &[IndHL, A], 1, 8, "----"), // 0x77
+    op("ld", &[A, B], 1, 4, "----"), // 0x78
+    op("ld", &[A, C], 1, 4, "----"), // 0x79
+    op("ld", &[A, D], 1, 4, "----"), // 0x7a
+    op("ld", &[A, E], 1, 4, "----"), // 0x7b
+    op("ld", &[A, H], 1, 4, "----"), // 0x7c
+    op("ld", &[A, L], 1, 4, "----"), // 0x7d
+    op("ld", &[A, IndHL], 1, 8, "----"), // 0x7e
+    op("ld", &[A, A], 1, 4, "----"), // 0x7f
+    op("add", &[A, B], 1, 4, "Z0HC"), // 0x80
+    op("add", &[A, C], 1, 4, "Z0HC"), // 0x81
+    op("add", &[A, D], 1, 4, "Z0HC"), // 0x82
+    op("add", &[A, E], 1, 4, "Z0HC"), // 0x83
+    op("add", &[A, H], 1, 4, "Z0HC"), // 0x84
+    op("add", &[A, L], 1, 4, "Z0HC"), // 0x85
+    op("add", &[A, IndHL], 1, 8, "Z0HC"), // 0x86
+    op("add", &[A, A], 1, 4, "Z0HC"), // 0x87
+    op("adc", &[A, B], 1, 4, "Z0HC"), // 0x88
+    op("adc", &[A, C], 1, 4, "Z0HC"), // 0x89
+    op("adc", &[A, D], 1, 4, "Z0HC"), // 0x8a
+    op("adc", &[A, E], 1, 4, "Z0HC"), // 0x8b
+    op("adc", &[A, H], 1, 4, "Z0HC"), // 0x8c
+    op("adc", &[A, L], 1, 4, "Z0HC"), // 0x8d
+    op("adc", &[A, IndHL], 1, 8, "Z0HC"), // 0x8e
+    op("adc", &[A, A], 1, 4, "Z0HC"), // 0x8f
+    op("sub", &[A, B], 1, 4, "Z1HC"), // 0x90
+    op("sub", &[A, C], 1, 4, "Z1HC"), // 0x91
+    op("sub", &[A, D], 1, 4, "Z1HC"), // 0x92
+    op("sub", &[A, E], 1, 4, "Z1HC"), // 0x93
+    op("sub", &[A, H], 1, 4, "Z1HC"), // 0x94
+    op("sub", &[A, L], 1, 4, "Z1HC"), // 0x95
+    op("sub", &[A, IndHL], 1, 8, "Z1HC"), // 0x96
+    op("sub", &[A, A], 1, 4, "Z1HC"), // 0x97
+    op("sbc", &[A, B], 1, 4, "Z1HC"), // 0x98
+    op("sbc", &[A, C], 1, 4, "Z1HC"), // 0x99
+    op("sbc", &[A, D], 1, 4, "Z1HC"), // 0x9a
+    op("sbc", &[A, E], 1, 4, "Z1HC"), // 0x9b
+    op("sbc", &[A, H], 1, 4, "Z1HC"), // 0x9c
+    op("sbc", &[A, L], 1, 4, "Z1HC"), // 0x9d
+    op("sbc", &[A, IndHL], 1, 8, "Z1HC"), // 0x9e
+    op("sbc", &[A, A], 1, 4, "Z1HC"), // 0x9f
+    op("and", &[A, B], 1, 4, "Z010"), // 0xa0
+    op("and", &[A, C], 1, 4, "Z010"), // 0xa1
+    op("and", &[A, D], 1, 4, "Z010"), // 0xa2
+    op("and", &[A, E], 1, 4, "Z010"), // 0xa3
+    op("and", &[A, H], 1, 4, "Z010"), // 0xa4
+    op("and", &[A, L], 1, 4, "Z010"), // 0xa5
+    op("and", &[A, IndHL], 1, 8, "Z010"), // 0xa6
+    op("and", &[A, A], 1, 4, "Z010"), // 0xa7
+    op("xor", &[A, B], 1, 4, "Z000"), // 0xa8
+    op("xor", &[A, C], 1, 4, "Z000"), // 0xa9
+    op("xor", &[A, D], 1, 4, "Z000"), // 0xaa
+    op("xor", &[A, E], 1, 4, "Z000"), // 0xab
+    op("xor", &[A, H], 1, 4, "Z000"), // 0xac
+    op("xor", &[A, L], 1, 4, "Z000"), // 0xad
+    op("xor", &[A, IndHL], 1, 8, "Z000"), // 0xae
+    op("xor", &[A, A], 1, 4, "Z000"), // 0xaf
+    op("or", &[A, B], 1, 4, "Z000"), // 0xb0
+    op("or", &[A, C], 1, 4, "Z000"), // 0xb1
+    op("or", &[A, D], 1, 4, "Z000"), // 0xb2
+    op("or", &[A, E], 1, 4, "Z000"), // 0xb3
+    op("or", &[A, H], 1, 4, "Z000"), // 0xb4
+    op("or", &[A, L], 1, 4, "Z000"), // 0xb5
+    op("or", &[A, IndHL], 1, 8, "Z000"), // 0xb6
+    op("or", &[A, A], 1, 4, "Z000"), // 0xb7
+    op("cp", &[A, B], 1, 4, "Z1HC"), // 0xb8
+    op("cp", &[A, C], 1, 4, "Z1HC"), // 0xb9
+    op("cp", &[A, D], 1, 4, "Z1HC"), // 0xba
+    op("cp", &[A, E], 1, 4, "Z1HC"), // 0xbb
+    op("cp", &[A, H], 1, 4, "Z1HC"), // 0xbc
+    op("cp", &[A, L], 1, 4, "Z1HC"), // 0xbd
+    op("cp", &[A, IndHL], 1, 8, "Z1HC"), // 0xbe
+    op("cp", &[A, A], 1, 4, "Z1HC"), // 0xbf
+    branch("ret", &[CondNZ], 1, 8, 20), // 0xc0
+    op("pop", &[BC], 1, 12, "----"), // 0xc1
+    branch("jp", &[CondNZ, Imm16], 3, 12, 16), // 0xc2
+    op("jp", &[Imm16], 3, 16, "----"), // 0xc3
+    branch("call", &[CondNZ, Imm16], 3, 12, 24), // 0xc4
+    op("push", &[BC], 1, 16, "----"), // 0xc5
+    op("add", &[A, Imm8], 2, 8, "Z0HC"), // 0xc6
+    op("rst", &[Vector(0x00)], 1, 16, "----"), // 0xc7
+    branch("ret", &[CondZ], 1, 8, 20), // 0xc8
+    op("ret", &[], 1, 16, "----"), // 0xc9
+    branch("jp", &[CondZ, Imm16], 3, 12, 16), // 0xca
+    op("prefix", &[], 1, 4, "----"), // 0xcb
+    branch("call", &[CondZ, Imm16], 3, 12, 24), // 0xcc
+    op("call", &[Imm16], 3, 24, "----"), // 0xcd
+    op("adc", &[A, Imm8], 2, 8, "Z0HC"), // 0xce
+    op("rst", &[Vector(0x08)], 1, 16, "----"), // 0xcf
+    branch("ret", &[CondNC], 1, 8, 20), // 0xd0
+    op("pop", &[DE], 1, 12, "----"), // 0xd1
+    branch("jp", &[CondNC, Imm16], 3, 12, 16), // 0xd2
+    ILLEGAL, // 0xd3
+    branch("call", &[CondNC, Imm16], 3, 12, 24), // 0xd4
+    op("push", &[DE], 1, 16, "----"), // 0xd5
+    op("sub", &[A, Imm8], 2, 8, "Z1HC"), // 0xd6
+    op("rst", &[Vector(0x10)], 1, 16, "----"), // 0xd7
+    branch("ret", &[CondC], 1, 8, 20), // 0xd8
+    op("reti", &[], 1, 16, "----"), // 0xd9
+    branch("jp", &[CondC, Imm16], 3, 12, 16), // 0xda
+    ILLEGAL, // 0xdb
+    branch("call", &[CondC, Imm16], 3, 12, 24), // 0xdc
+    ILLEGAL, // 0xdd
+    op("sbc", &[A, Imm8], 2, 8, "Z1HC"), // 0xde
+    op("rst", &[Vector(0x18)], 1, 16, "----"), // 0xdf
+    op("ldh", &[IndImm8, A], 2, 12, "----"), // 0xe0
+    op("pop", &[HL], 1, 12, "----"), // 0xe1
+    op("ldh", &[IndC, A], 1, 8, "----"), // 0xe2
+    ILLEGAL, // 0xe3
+    ILLEGAL, // 0xe4
+    op("push", &[HL], 1, 16, "----"), // 0xe5
+    op("and", &[A, Imm8], 2, 8, "Z010"), // 0xe6
+    op("rst", &[Vector(0x20)], 1, 16, "----"), // 0xe7
+    op("add", &[SP, SImm8], 2, 16, "00HC"), // 0xe8
+    op("jp", &[HL], 1, 4, "----"), // 0xe9
+    op("ld", &[IndImm16, A], 3, 16, "----"), // 0xea
+    ILLEGAL, // 0xeb
+    ILLEGAL, // 0xec
+    ILLEGAL, // 0xed
+    op("xor", &[A, Imm8], 2, 8, "Z000"), // 0xee
+    op("rst", &[Vector(0x28)], 1, 16, "----"), // 0xef
+    op("ldh", &[A, IndImm8], 2, 12, "----"), // 0xf0
+    op("pop", &[AF], 1, 12, "ZNHC"), // 0xf1
+    op("ldh", &[A, IndC], 1, 8, "----"), // 0xf2
+    op("di", &[], 1, 4, "----"), // 0xf3
+    ILLEGAL, // 0xf4
+    op("push", &[AF], 1, 16, "----"), // 0xf5
+    op("or", &[A, Imm8], 2, 8, "Z000"), // 0xf6
+    op("rst", &[Vector(0x30)], 1, 16, "----"), // 0xf7
+    op("ld", &[HL, SPImm8], 2, 12, "00HC"), // 0xf8
+    op("ld", &[SP, HL], 1, 8, "----"), // 0xf9
+    op("ld", &[A, IndImm16], 3, 16, "----"), // 0xfa
+    op("ei", &[], 1, 4, "----"), // 0xfb
+    ILLEGAL, // 0xfc
+    ILLEGAL, // 0xfd
+    op("cp", &[A, Imm8], 2, 8, "Z1HC"), // 0xfe
+    op("rst", &[Vector(0x38)], 1, 16, "----"), // 0xff
+];
+
+static CB_OPCODES: [OpInfo; 256] = [
+    op("rlc", &[B], 2, 8, "Z00C"), // 0x00
+    op("rlc", &[C], 2, 8, "Z00C"), // 0x01
+    op("rlc", &[D], 2, 8, "Z00C"), // 0x02
+    op("rlc", &[E], 2, 8, "Z00C"), // 0x03
+    op("rlc", &[H], 2, 8, "Z00C"), // 0x04
+    op("rlc", &[L], 2, 8, "Z00C"), // 0x05
+    op("rlc", &[IndHL], 2, 16, "Z00C"), // 0x06
+    op("rlc", &[A], 2, 8, "Z00C"), // 0x07
+    op("rrc", &[B], 2, 8, "Z00C"), // 0x08
+    op("rrc", &[C], 2, 8, "Z00C"), // 0x09
+    op("rrc", &[D], 2, 8, "Z00C"), // 0x0a
+    op("rrc", &[E], 2, 8, "Z00C"), // 0x0b
+    op("rrc", &[H], 2, 8, "Z00C"), // 0x0c
+    op("rrc", &[L], 2, 8, "Z00C"), // 0x0d
+    op("rrc", &[IndHL], 2, 16, "Z00C"), // 0x0e
+    op("rrc", &[A], 2, 8, "Z00C"), // 0x0f
+    op("rl", &[B], 2, 8, "Z00C"), // 0x10
+    op("rl", &[C], 2, 8, "Z00C"), // 0x11
+    op("rl", &[D], 2, 8, "Z00C"), // 0x12
+    op("rl", &[E], 2, 8, "Z00C"), // 0x13
+    op("rl", &[H], 2, 8, "Z00C"), // 0x14
+    op("rl", &[L], 2, 8, "Z00C"), // 0x15
+    op("rl", &[IndHL], 2, 16, "Z00C"), // 0x16
+    op("rl", &[A], 2, 8, "Z00C"), // 0x17
+    op("rr", &[B], 2, 8, "Z00C"), // 0x18
+    op("rr", &[C], 2, 8, "Z00C"), // 0x19
+    op("rr", &[D], 2, 8, "Z00C"), // 0x1a
+    op("rr", &[E], 2, 8, "Z00C"), // 0x1b
+    op("rr", &[H], 2, 8, "Z00C"), // 0x1c
+    op("rr", &[L], 2, 8, "Z00C"), // 0x1d
+    op("rr", &[IndHL], 2, 16, "Z00C"), // 0x1e
+    op("rr", &[A], 2, 8, "Z00C"), // 0x1f
+    op("sla", &[B], 2, 8, "Z00C"), // 0x20
+    op("sla", &[C], 2, 8, "Z00C"), // 0x21
+    op("sla", &[D], 2, 8, "Z00C"), // 0x22
+    op("sla", &[E], 2, 8, "Z00C"), // 0x23
+    op("sla", &[H], 2, 8, "Z00C"), // 0x24
+    op("sla", &[L], 2, 8, "Z00C"), // 0x25
+    op("sla", &[IndHL], 2, 16, "Z00C"), // 0x26
+    op("sla", &[A], 2, 8, "Z00C"), // 0x27
+    op("sra", &[B], 2, 8, "Z00C"), // 0x28
+    op("sra", &[C], 2, 8, "Z00C"), // 0x29
+    op("sra", &[D], 2, 8, "Z00C"), // 0x2a
+    op("sra", &[E], 2, 8, "Z00C"), // 0x2b
+    op("sra", &[H], 2, 8, "Z00C"), // 0x2c
+    op("sra", &[L], 2, 8, "Z00C"), // 0x2d
+    op("sra", &[IndHL], 2, 16, "Z00C"), // 0x2e
+    op("sra", &[A], 2, 8, "Z00C"), // 0x2f
+    op("swap", &[B], 2, 8, "Z000"), // 0x30
+    op("swap", &[C], 2, 8, "Z000"), // 0x31
+    op("swap", &[D], 2, 8, "Z000"), // 0x32
+    op("swap", &[E], 2, 8, "Z000"), // 0x33
+    op("swap", &[H], 2, 8, "Z000"), // 0x34
+    op("swap", &[L], 2, 8, "Z000"), // 0x35
+    op("swap", &[IndHL], 2, 16, "Z000"), // 0x36
+    op("swap", &[A], 2, 8, "Z000"), // 0x37
+    op("srl", &[B], 2, 8, "Z00C"), // 0x38
+    op("srl", &[C], 2, 8, "Z00C"), // 0x39
+    op("srl", &[D], 2, 8, "Z00C"), // 0x3a
+    op("srl", &[E], 2, 8, "Z00C"), // 0x3b
+    op("srl", &[H], 2, 8, "Z00C"), // 0x3c
+    op("srl", &[L], 2, 8, "Z00C"), // 0x3d
+    op("srl", &[IndHL], 2, 16, "Z00C"), // 0x3e
+    op("srl", &[A], 2, 8, "Z00C"), // 0x3f
+    op("bit", &[Bit(0), B], 2, 8, "Z01-"), // 0x40
+    op("bit", &[Bit(0), C], 2, 8, "Z01-"), // 0x41
+    op("bit", &[Bit(0), D], 2, 8, "Z01-"), // 0x42
+    op("bit", &[Bit(0), E], 2, 8, "Z01-"), // 0x43
+    op("bit", &[Bit(0), H], 2, 8, "Z01-"), // 0x44
+    op("bit", &[Bit(0), L], 2, 8, "Z01-"), // 0x45
+    op("bit", &[Bit(0), IndHL], 2, 12, "Z01-"), // 0x46
+    op("bit", &[Bit(0), A], 2, 8, "Z01-"), // 0x47
+    op("bit", &[Bit(1), B], 2, 8, "Z01-"), // 0x48
+    op("bit", &[Bit(1), C], 2, 8, "Z01-"), // 0x49
+    op("bit", &[Bit(1), D], 2, 8, "Z01-"), // 0x4a
+    op("bit", &[Bit(1), E], 2, 8, "Z01-"), // 0x4b
+    op("bit", &[Bit(1), H], 2, 8, "Z01-"), // 0x4c
+    op("bit", &[Bit(1), L], 2, 8, "Z01-"), // 0x4d
+    op("bit", &[Bit(1), IndHL], 2, 12, "Z01-"), // 0x4e
+    op("bit", &[Bit(1), A], 2, 8, "Z01-"), // 0x4f
+    op("bit", &[Bit(2), B], 2, 8, "Z01-"), // 0x50
+    op("bit", &[Bit(2), C], 2, 8, "Z01-"), // 0x51
+    op("bit", &[Bit(2), D], 2, 8, "Z01-"), // 0x52
+    op("bit", &[Bit(2), E], 2, 8, "Z01-"), // 0x53
+    op("bit", &[Bit(2), H], 2, 8, "Z01-"), // 0x54
+    op("bit", &[Bit(2), L], 2, 8, "Z01-"), // 0x55
+    op("bit", &[Bit(2), IndHL], 2, 12, "Z01-"), // 0x56
+    op("bit", &[Bit(2), A], 2, 8, "Z01-"), // 0x57
+    op("bit", &[Bit(3), B], 2, 8, "Z01-"), // 0x58
+    op("bit", &[Bit(3), C], 2, 8, "Z01-"), // 0x59
+    op("bit", &[Bit(3), D], 2, 8, "Z01-"), // 0x5a
+    op("bit", &[Bit(3), E], 2, 8, "Z01-"), // 0x5b
+    op("bit", &[Bit(3), H], 2, 8, "Z01-"), // 0x5c
+    op("bit", &[Bit(3), L], 2, 8, "Z01-"), // 0x5d
+    op("bit", &[Bit(3), IndHL], 2, 12, "Z01-"), // 0x5e
+    op("bit", &[Bit(3), A], 2, 8, "Z01-"), // 0x5f
+    op("bit", &[Bit(4), B], 2, 8, "Z01-"), // 0x60
+    op("bit", &[Bit(4), C], 2, 8, "Z01-"), // 0x61
+    op("bit", &[Bit(4), D], 2, 8, "Z01-"), // 0x62
+    op("bit", &[Bit(4), E], 2, 8, "Z01-"), // 0x63
+    op("bit", &[Bit(4), H], 2, 8, "Z01-"), // 0x64
+    op("bit", &[Bit(4), L], 2, 8, "Z01-"), // 0x65
+    op("bit", &[Bit(4), IndHL], 2, 12, "Z01-"), // 0x66
+    op("bit", &[Bit(4), A], 2, 8, "Z01-"), // 0x67
+    op("bit", &[Bit(5), B], 2, 8, "Z01-"), // 0x68
+    op("bit", &[Bit(5), C], 2, 8, "Z01-"), // 0x69
+    op("bit", &[Bit(5), D], 2, 8, "Z01-"), // 0x6a
+    op("bit", &[Bit(5), E], 2, 8, "Z01-"), // 0x6b
+    op("bit", &[Bit(5), H], 2, 8, "Z01-"), // 0x6c
+    op("bit", &[Bit(5), L], 2, 8, "Z01-"), // 0x6d
+    op("bit", &[Bit(5), IndHL], 2, 12, "Z01-"), // 0x6e
+    op("bit", &[Bit(5), A], 2, 8, "Z01-"), // 0x6f
+    op("bit", &[Bit(6), B], 2, 8, "Z01-"), // 0x70
+    op("bit", &[Bit(6), C], 2, 8, "Z01-"), // 0x71
+    op("bit", &[Bit(6), D], 2, 8, "Z01-"), // 0x72
+    op("bit", &[Bit(6), E], 2, 8, "Z01-"), // 0x73
+    op("bit", &[Bit(6), H], 2, 8, "Z01-"), // 0x74
+    op("bit", &[Bit(6), L], 2, 8, "Z01-"), // 0x75
+    op("bit", &[Bit(6), IndHL], 2, 12, "Z01-"), // 0x76
+    op("bit", &[Bit(6), A], 2, 8, "Z01-"), // 0x77
+    op("bit", &[Bit(7), B], 2, 8, "Z01-"), // 0x78
+    op("bit", &[Bit(7), C], 2, 8, "Z01-"), // 0x79
+    op("bit", &[Bit(7), D], 2, 8, "Z01-"), // 0x7a
+    op("bit", &[Bit(7), E], 2, 8, "Z01-"), // 0x7b
+    op("bit", &[Bit(7), H], 2, 8, "Z01-"), // 0x7c
+    op("bit", &[Bit(7), L], 2, 8, "Z01-"), // 0x7d
+    op("bit", &[Bit(7), IndHL], 2, 12, "Z01-"), // 0x7e
+    op("bit", &[Bit(7), A], 2, 8, "Z01-"), // 0x7f
+    op("res", &[Bit(0), B], 2, 8, "----"), // 0x80
+    op("res", &[Bit(0), C], 2, 8, "----"), // 0x81
+    op("res", &[Bit(0), D], 2, 8, "----"), // 0x82
+    op("res", &[Bit(0), E], 2, 8, "----"), // 0x83
+    op("res", &[Bit(0), H], 2, 8, "----"), // 0x84
+    op("res", &[Bit(0), L], 2, 8, "----"), // 0x85
+    op("res", &[Bit(0), IndHL], 2, 16, "----"), // 0x86
+    op("res", &[Bit(0), A], 2, 8, "----"), // 0x87
+    op("res", &[Bit(1), B], 2, 8, "----"), // 0x88
+    op("res", &[Bit(1), C], 2, 8, "----"), // 0x89
+    op("res", &[Bit(1), D], 2, 8, "----"), // 0x8a
+    op("res", &[Bit(1), E], 2, 8, "----"), // 0x8b
+    op("res", &[Bit(1), H], 2, 8, "----"), // 0x8c
+    op("res", &[Bit(1), L], 2, 8, "----"), // 0x8d
+    op("res", &[Bit(1), IndHL], 2, 16, "----"), // 0x8e
+    op("res", &[Bit(1), A], 2, 8, "----"), // 0x8f
+    op("res", &[Bit(2), B], 2, 8, "----"), // 0x90
+    op("res", &[Bit(2), C], 2, 8, "----"), // 0x91
+    op("res", &[Bit(2), D], 2, 8, "----"), // 0x92
+    op("res", &[Bit(2), E], 2, 8, "----"), // 0x93
+    op("res", &[Bit(2), H], 2, 8, "----"), // 0x94
+    op("res", &[Bit(2), L], 2, 8, "----"), // 0x95
+    op("res", &[Bit(2), IndHL], 2, 16, "----"), // 0x96
+    op("res", &[Bit(2), A], 2, 8, "----"), // 0x97
+    op("res", &[Bit(3), B], 2, 8, "----"), // 0x98
+    op("res", &[Bit(3), C], 2, 8, "----"), // 0x99
+    op("res", &[Bit(3), D], 2, 8, "----"), // 0x9a
+    op("res", &[Bit(3), E], 2, 8, "----"), // 0x9b
+    op("res", &[Bit(3), H], 2, 8, "----"), // 0x9c
+    op("res", &[Bit(3), L], 2, 8, "----"), // 0x9d
+    op("res", &[Bit(3), IndHL], 2, 16, "----"), // 0x9e
+    op("res", &[Bit(3), A], 2, 8, "----"), // 0x9f
+    op("res", &[Bit(4), B], 2, 8, "----"), // 0xa0
+    op("res", &[Bit(4), C], 2, 8, "----"), // 0xa1
+    op("res", &[Bit(4), D], 2, 8, "----"), // 0xa2
+    op("res", &[Bit(4), E], 2, 8, "----"), // 0xa3
+    op("res", &[Bit(4), H], 2, 8, "----"), // 0xa4
+    op("res", &[Bit(4), L], 2, 8, "----"), // 0xa5
+    op("res", &[Bit(4), IndHL], 2, 16, "----"), // 0xa6
+    op("res", &[Bit(4), A], 2, 8, "----"), // 0xa7
+    op("res", &[Bit(5), B], 2, 8, "----"), // 0xa8
+    op("res", &[Bit(5), C], 2, 8, "----"), // 0xa9
+    op("res", &[Bit(5), D], 2, 8, "----"), // 0xaa
+    op("res", &[Bit(5), E], 2, 8, "----"), // 0xab
+    op("res", &[Bit(5), H], 2, 8, "----"), // 0xac
+    op("res", &[Bit(5), L], 2, 8, "----"), // 0xad
+    op("res", &[Bit(5), IndHL], 2, 16, "----"), // 0xae
+    op("res", &[Bit(5), A], 2, 8, "----"), // 0xaf
+    op("res", &[Bit(6), B], 2, 8, "----"), // 0xb0
+    op("res", &[Bit(6), C], 2, 8, "----"), // 0xb1
+    op("res", &[Bit(6), D], 2, 8, "----"), // 0xb2
+    op("res", &[Bit(6), E], 2, 8, "----"), // 0xb3
+    op("res", &[Bit(6), H], 2, 8, "----"), // 0xb4
+    op("res", &[Bit(6), L], 2, 8, "----"), // 0xb5
+    op("res", &[Bit(6), IndHL], 2, 16, "----"), // 0xb6
+    op("res", &[Bit(6), A], 2, 8, "----"), // 0xb7
+    op("res", &[Bit(7), B], 2, 8, "----"), // 0xb8
+    op("res", &[Bit(7), C], 2, 8, "----"), // 0xb9
+    op("res", &[Bit(7), D], 2, 8, "----"), // 0xba
+    op("res", &[Bit(7), E], 2, 8, "----"), // 0xbb
+    op("res", &[Bit(7), H], 2, 8, "----"), // 0xbc
+    op("res", &[Bit(7), L], 2, 8, "----"), // 0xbd
+    op("res", &[Bit(7), IndHL], 2, 16, "----"), // 0xbe
+    op("res", &[Bit(7), A], 2, 8, "----"), // 0xbf
+    op("set", &[Bit(0), B], 2, 8, "----"), // 0xc0
+    op("set", &[Bit(0), C], 2, 8, "----"), // 0xc1
+    op("set", &[Bit(0), D], 2, 8, "----"), // 0xc2
+    op("set", &[Bit(0), E], 2, 8, "----"), // 0xc3
+    op("set", &[Bit(0), H], 2, 8, "----"), // 0xc4
+    op("set", &[Bit(0), L], 2, 8, "----"), // 0xc5
+    op("set", &[Bit(0), IndHL], 2, 16, "----"), // 0xc6
+    op("set", &[Bit(0), A], 2, 8, "----"), // 0xc7
+    op("set", &[Bit(1), B], 2, 8, "----"), // 0xc8
+    op("set", &[Bit(1), C], 2, 8, "----"), // 0xc9
+    op("set", &[Bit(1), D], 2, 8, "----"), // 0xca
+    op("set", &[Bit(1), E], 2, 8, "----"), // 0xcb
+    op("set", &[Bit(1), H], 2, 8, "----"), // 0xcc
+    op("set", &[Bit(1), L], 2, 8, "----"), // 0xcd
+    op("set", &[Bit(1), IndHL], 2, 16, "----"), // 0xce
+    op("set", &[Bit(1), A], 2, 8, "----"), // 0xcf
+    op("set", &[Bit(2), B], 2, 8, "----"), // 0xd0
+    op("set", &[Bit(2), C], 2, 8, "----"), // 0xd1
+    op("set", &[Bit(2), D], 2, 8, "----"), // 0xd2
+    op("set", &[Bit(2), E], 2, 8, "----"), // 0xd3
+    op("set", &[Bit(2), H], 2, 8, "----"), // 0xd4
+    op("set", &[Bit(2), L], 2, 8, "----"), // 0xd5
+    op("set", &[Bit(2), IndHL], 2, 16, "----"), // 0xd6
+    op("set", &[Bit(2), A], 2, 8, "----"), // 0xd7
+    op("set", &[Bit(3), B], 2, 8, "----"), // 0xd8
+    op("set", &[Bit(3), C], 2, 8, "----"), // 0xd9
+    op("set", &[Bit(3), D], 2, 8, "----"), // 0xda
+    op("set", &[Bit(3), E], 2, 8, "----"), // 0xdb
+    op("set", &[Bit(3), H], 2, 8, "----"), // 0xdc
+    op("set", &[Bit(3), L], 2, 8, "----"), // 0xdd
+    op("set", &[Bit(3), IndHL], 2, 16, "----"), // 0xde
+    op("set", &[Bit(3), A], 2, 8, "----"), // 0xdf
+    op("set", &[Bit(4), B], 2, 8, "----"), // 0xe0
+    op("set", &[Bit(4), C], 2, 8, "----"), // 0xe1
+    op("set", &[Bit(4), D], 2, 8, "----"), // 0xe2
+    op("set", &[Bit(4), E], 2, 8, "----"), // 0xe3
+    op("set", &[Bit(4), H], 2, 8, "----"), // 0xe4
+    op("set", &[Bit(4), L], 2, 8, "----"), // 0xe5
+    op("set", &[Bit(4), IndHL], 2, 16, "----"), // 0xe6
+    op("set", &[Bit(4), A], 2, 8, "----"), // 0xe7
+    op("set", &[Bit(5), B], 2, 8, "----"), // 0xe8
+    op("set", &[Bit(5), C], 2, 8, "----"), // 0xe9
+    op("set", &[Bit(5), D], 2, 8, "----"), // 0xea
+    op("set", &[Bit(5), E], 2, 8, "----"), // 0xeb
+    op("set", &[Bit(5), H], 2, 8, "----"), // 0xec
+    op("set", &[Bit(5), L], 2, 8, "----"), // 0xed
+    op("set", &[Bit(5), IndHL], 2, 16, "----"), // 0xee
+    op("set", &[Bit(5), A], 2, 8, "----"), // 0xef
+    op("set", &[Bit(6), B], 2, 8, "----"), // 0xf0
+    op("set", &[Bit(6), C], 2, 8, "----"), // 0xf1
+    op("set", &[Bit(6), D], 2, 8, "----"), // 0xf2
+    op("set", &[Bit(6), E], 2, 8, "----"), // 0xf3
+    op("set", &[Bit(6), H], 2, 8, "----"), // 0xf4
+    op("set", &[Bit(6), L], 2, 8, "----"), // 0xf5
+    op("set", &[Bit(6), IndHL], 2, 16, "----"), // 0xf6
+    op("set", &[Bit(6), A], 2, 8, "----"), // 0xf7
+    op("set", &[Bit(7), B], 2, 8, "----"), // 0xf8
+    op("set", &[Bit(7), C], 2, 8, "----"), // 0xf9
+    op("set", &[Bit(7), D], 2, 8, "----"), // 0xfa
+    op("set", &[Bit(7), E], 2, 8, "----"), // 0xfb
+    op("set", &[Bit(7), H], 2, 8, "----"), // 0xfc
+    op("set", &[Bit(7), L], 2, 8, "----"), // 0xfd
+    op("set", &[Bit(7), IndHL], 2, 16, "----"), // 0xfe
+    op("set", &[Bit(7), A], 2, 8, "----"), // 0xff
+];
@@ -0,0 +1,83 @@
+//! Keeps system time and the next deadline of every piece of hardware that
+//! does something on its own, so nothing has to be polled between them.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// PPU mode change
+    PPU,
+    /// TIMA overflow reload and its interrupt
+    Timer,
+    /// Serial transfer done
+    Serial,
+    /// Cartridge real-time clock catches up
+    RTC,
+}
+
+const EVENT_COUNT: usize = 4;
+
+static EVENTS: [Event; EVENT_COUNT] = [Event::PPU, Event::Timer, Event::Serial, Event::RTC];
+
+pub struct Scheduler {
+    /// T-cycles since power on
+    now: u64,
+    deadlines: [Option<u64>; EVENT_COUNT],
+    /// Earliest of `deadlines`, u64::MAX when there's nothing scheduled
+    next: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { now: 0, deadlines: [None; EVENT_COUNT], next: u64::MAX }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Run `event` `cycles` T-cycles from now, replacing any earlier deadline for it
+    pub fn schedule(&mut self, event: Event, cycles: u64) {
+        self.schedule_at(event, self.now + cycles);
+    }
+
+    pub fn schedule_at(&mut self, event: Event, at: u64) {
+        self.deadlines[event as usize] = Some(at);
+        self.update_next();
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.deadlines[event as usize] = None;
+        self.update_next();
+    }
+
+    pub fn deadline(&self, event: Event) -> Option<u64> {
+        self.deadlines[event as usize]
+    }
+
+    pub fn next_deadline(&self) -> u64 {
+        self.next
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Earliest event whose deadline has passed and that deadline, it's
+    /// removed and has to be scheduled again by whoever handles it
+    pub fn pop_due(&mut self) -> Option<(Event, u64)> {
+        if self.next > self.now {
+            return None;
+        }
+        let event = EVENTS
+            .iter()
+            .copied()
+            .filter(|&event| self.deadlines[event as usize].is_some_and(|at| at <= self.now))
+            .min_by_key(|&event| self.deadlines[event as usize])?;
+        let at = self.deadlines[event as usize]?;
+        self.cancel(event);
+        Some((event, at))
+    }
+
+    fn update_next(&mut self) {
+        self.next = self.deadlines.iter().flatten().copied().min().unwrap_or(u64::MAX);
+    }
+}
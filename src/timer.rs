@@ -0,0 +1,268 @@
+//! DIV, TIMA, TMA and TAC. DIV is the upper byte of a 16-bit counter running
+//! at the CPU clock, TIMA counts falling edges of one of the counter's bits.
+//!
+//! The timer is only brought up to date when it's accessed or its overflow
+//! comes due, see `sync`.
+
+/// Counter bit whose falling edge increments TIMA, by TAC clock select
+static TAC_BIT: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+#[derive(Clone, Copy)]
+pub struct Timer {
+    /// Scheduler time the rest of the state is for
+    synced_at: u64,
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed and reads 0, it's reloaded on the next M-cycle
+    overflowed: bool,
+    /// TIMA was reloaded this M-cycle, writes to it are lost and TMA writes
+    /// go through to it
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            synced_at: 0,
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloading: false,
+        }
+    }
+
+    /// Registers as of the last sync, see `synced` to read them as of now
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            0xff07 => self.tac | 0xf8,
+            _ => panic!("Not a timer register: 0x{:04X}", address),
+        }
+    }
+
+    /// Has to be synced to the time of the write first
+    pub fn write(&mut self, address: u16, val: u8) {
+        match address {
+            0xff04 => self.reset_div(),
+            0xff05 => {
+                if !self.reloading {
+                    // also cancels a pending reload
+                    self.tima = val;
+                    self.overflowed = false;
+                }
+            }
+            0xff06 => {
+                self.tma = val;
+                if self.reloading {
+                    self.tima = val;
+                }
+            }
+            0xff07 => {
+                // switching to a bit that's low, or disabling the timer, can
+                // look like a falling edge
+                let before = self.signal();
+                self.tac = val & 0x07;
+                if before && !self.signal() {
+                    self.increment();
+                }
+            }
+            _ => panic!("Not a timer register: 0x{:04X}", address),
+        }
+    }
+
+    /// Any write to DIV clears the whole counter, which increments TIMA if
+    /// the selected bit was set
+    pub fn reset_div(&mut self) {
+        let before = self.signal();
+        self.counter = 0;
+        if before {
+            self.increment();
+        }
+    }
+
+    /// Catch up to `now`, returns whether the timer interrupt was requested
+    /// on the way
+    pub fn sync(&mut self, now: u64) -> bool {
+        let interrupt = self.tick(now - self.synced_at);
+        self.synced_at = now;
+        interrupt
+    }
+
+    /// A copy caught up to `now`, for reading without syncing
+    pub fn synced(&self, now: u64) -> Timer {
+        let mut timer = *self;
+        timer.sync(now);
+        timer
+    }
+
+    /// Scheduler time at which the next timer interrupt will be requested,
+    /// assuming no more writes
+    pub fn next_interrupt(&self) -> Option<u64> {
+        if self.overflowed {
+            return Some(self.synced_at + 4);
+        }
+        if self.tac & 0x04 == 0 {
+            return None;
+        }
+        let period = self.period();
+        let to_edge = period - (self.counter as u64 % period);
+        let edges = 0x100 - self.tima as u64;
+        // the reload happens one M-cycle after the overflowing edge
+        Some(self.synced_at + to_edge + (edges - 1) * period + 4)
+    }
+
+    /// Advance by `cycles` T-cycles, returns whether the timer interrupt was requested
+    fn tick(&mut self, cycles: u64) -> bool {
+        let mut interrupt = false;
+        let mut remaining = cycles;
+        while remaining >= 4 {
+            if self.overflowed || self.reloading {
+                interrupt |= self.tick_mcycle();
+                remaining -= 4;
+                continue;
+            }
+            if self.tac & 0x04 == 0 {
+                // nothing but DIV moves
+                self.counter = self.counter.wrapping_add((remaining & !3) as u16);
+                break;
+            }
+            // skip ahead to the M-cycle with the next falling edge
+            let period = self.period();
+            let skip = (period - (self.counter as u64 % period) - 4).min(remaining - 4);
+            self.counter = self.counter.wrapping_add(skip as u16);
+            remaining -= skip;
+            interrupt |= self.tick_mcycle();
+            remaining -= 4;
+        }
+        interrupt
+    }
+
+    /// T-cycles between falling edges of the selected bit
+    fn period(&self) -> u64 {
+        TAC_BIT[(self.tac & 0x03) as usize] as u64 * 2
+    }
+
+    fn tick_mcycle(&mut self) -> bool {
+        let mut interrupt = false;
+        self.reloading = false;
+        if self.overflowed {
+            self.overflowed = false;
+            self.tima = self.tma;
+            self.reloading = true;
+            interrupt = true;
+        }
+
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment();
+        }
+        interrupt
+    }
+
+    /// Selected counter bit ANDed with the enable bit
+    fn signal(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & TAC_BIT[(self.tac & 0x03) as usize] != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflowed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_counts_every_256_cycles() {
+        let mut timer = Timer::new();
+        timer.tick(252);
+        assert_eq!(timer.read(0xff04), 0);
+        timer.tick(4);
+        assert_eq!(timer.read(0xff04), 1);
+        timer.write(0xff04, 0x12);
+        assert_eq!(timer.read(0xff04), 0);
+    }
+
+    #[test]
+    fn overflow_reloads_one_mcycle_late() {
+        let mut timer = Timer::new();
+        timer.write(0xff06, 0xab);
+        timer.write(0xff05, 0xff);
+        timer.write(0xff07, 0x05); // 16 cycles per increment
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read(0xff05), 0x00);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(0xff05), 0xab);
+    }
+
+    #[test]
+    fn tima_write_cancels_pending_reload() {
+        let mut timer = Timer::new();
+        timer.write(0xff06, 0xab);
+        timer.write(0xff05, 0xff);
+        timer.write(0xff07, 0x05);
+        timer.tick(16);
+        timer.write(0xff05, 0x42);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(0xff05), 0x42);
+    }
+
+    #[test]
+    fn div_write_with_selected_bit_set_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write(0xff07, 0x05);
+        timer.tick(8); // bit 3 set
+        let tima = timer.read(0xff05);
+        timer.write(0xff04, 0);
+        assert_eq!(timer.read(0xff05), tima + 1);
+    }
+
+    #[test]
+    fn disabling_with_selected_bit_set_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write(0xff07, 0x05);
+        timer.tick(8);
+        let tima = timer.read(0xff05);
+        timer.write(0xff07, 0x01);
+        assert_eq!(timer.read(0xff05), tima + 1);
+    }
+
+    #[test]
+    fn sync_matches_stepping_every_mcycle() {
+        let mut stepped = Timer::new();
+        stepped.write(0xff06, 0xf0);
+        stepped.write(0xff07, 0x05);
+        let mut skipped = stepped;
+        let mut stepped_interrupts = 0;
+        for now in (4..=10000).step_by(4) {
+            stepped_interrupts += stepped.sync(now) as u32;
+        }
+        assert!(skipped.sync(10000));
+        assert_eq!(stepped_interrupts, 24);
+        for address in 0xff04..=0xff07 {
+            assert_eq!(skipped.read(address), stepped.read(address));
+        }
+    }
+
+    #[test]
+    fn next_interrupt_is_exact() {
+        let mut timer = Timer::new();
+        timer.write(0xff05, 0xfd);
+        timer.write(0xff07, 0x06); // 64 cycles per increment
+        let at = timer.next_interrupt().unwrap();
+        assert!(!timer.sync(at - 4));
+        assert!(timer.sync(at));
+    }
+}
@@ -0,0 +1,203 @@
+//! Instruction trace in the gameboy-doctor format, one line per executed
+//! instruction with the state before it runs:
+//!
+//! `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    /// Only trace instructions at these addresses
+    pub pc_range: Option<RangeInclusive<u16>>,
+    /// Only trace instructions running from this ROM bank
+    pub bank: Option<usize>,
+    /// First frame to trace, counting from 0
+    pub start_frame: u64,
+    /// Frame at which tracing stops, if any
+    pub stop_frame: Option<u64>,
+    /// Frames completed so far
+    frame: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer {
+            out,
+            pc_range: None,
+            bank: None,
+            start_frame: 0,
+            stop_frame: None,
+            frame: 0,
+        }
+    }
+
+    fn should_trace<B: Bus>(&self, pc: u16, mem: &B) -> bool {
+        // gameboy-doctor logs start at $0100 with the post-boot state
+        if mem.boot_rom_mapped() {
+            return false;
+        }
+        let frame = self.frame;
+        if frame < self.start_frame || self.stop_frame.is_some_and(|stop| frame >= stop) {
+            return false;
+        }
+        if let Some(range) = &self.pc_range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+        if let Some(bank) = self.bank {
+            let current = match pc {
+                0x0000..=0x3fff => 0,
+                0x4000..=0x7fff => mem.rom_bank(),
+                _ => return false,
+            };
+            if current != bank {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Log the instruction `cpu` is about to execute
+    pub fn trace<B: Bus>(&mut self, cpu: &CPU, mem: &B) {
+        if !self.should_trace(cpu.pc, mem) {
+            return;
+        }
+        let pcmem: Vec<String> = (0..4)
+            .map(|i| format!("{:02X}", mem.read8(cpu.pc.wrapping_add(i))))
+            .collect();
+        writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            cpu.reg.a, cpu.reg.f, cpu.reg.b, cpu.reg.c, cpu.reg.d, cpu.reg.e, cpu.reg.h, cpu.reg.l,
+            cpu.sp, cpu.pc, pcmem.join(",")
+        )
+        .expect("Couldn't write trace");
+    }
+
+    pub fn flush(&mut self) {
+        self.out.flush().expect("Couldn't write trace");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Lets the test read back what the tracer wrote
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// FlatBus with a ROM bank mapped at $4000
+    struct BankedBus(FlatBus, usize);
+
+    impl Bus for BankedBus {
+        fn read8(&self, address: u16) -> u8 {
+            self.0.read8(address)
+        }
+
+        fn write8(&mut self, address: u16, value: u8) {
+            self.0.write8(address, value);
+        }
+
+        fn tick(&mut self, _cycles: u16) {}
+
+        fn rom_bank(&self) -> usize {
+            self.1
+        }
+    }
+
+    fn tracer() -> (Tracer, Rc<RefCell<Vec<u8>>>) {
+        let sink = Rc::new(RefCell::new(vec![]));
+        (Tracer::new(Box::new(SharedSink(sink.clone()))), sink)
+    }
+
+    /// Trace an instruction at each of `pcs`, returns the PCs that were logged
+    fn traced_pcs<B: Bus>(tracer: &mut Tracer, sink: &Rc<RefCell<Vec<u8>>>, mem: &B, pcs: &[u16]) -> Vec<u16> {
+        let mut cpu = CPU::new();
+        for &pc in pcs {
+            cpu.pc = pc;
+            tracer.trace(&cpu, mem);
+        }
+        let log = String::from_utf8(sink.borrow_mut().split_off(0)).unwrap();
+        log.lines()
+            .map(|line| u16::from_str_radix(&line[line.find("PC:").unwrap() + 3..][..4], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lines_match_gameboy_doctor() {
+        let (mut tracer, sink) = tracer();
+        let mut mem = FlatBus::new();
+        mem.data[0x0100..0x0104].copy_from_slice(&[0x00, 0xc3, 0x13, 0x02]);
+        let mut cpu = CPU::new();
+        cpu.reg.a = 0x01;
+        cpu.reg.f = 0xb0;
+        cpu.reg.c = 0x13;
+        cpu.reg.e = 0xd8;
+        cpu.reg.h = 0x01;
+        cpu.reg.l = 0x4d;
+        cpu.sp = 0xfffe;
+        cpu.pc = 0x0100;
+        tracer.trace(&cpu, &mem);
+        assert_eq!(
+            String::from_utf8(sink.borrow().clone()).unwrap(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02\n"
+        );
+    }
+
+    #[test]
+    fn pc_range_filter() {
+        let (mut tracer, sink) = tracer();
+        tracer.pc_range = Some(0x0150..=0x0200);
+        let pcs = traced_pcs(&mut tracer, &sink, &FlatBus::new(), &[0x0100, 0x014f, 0x0150, 0x0200, 0x0201]);
+        assert_eq!(pcs, [0x0150, 0x0200]);
+    }
+
+    #[test]
+    fn rom_bank_filter() {
+        let (mut tracer, sink) = tracer();
+        tracer.bank = Some(2);
+        let pcs = traced_pcs(&mut tracer, &sink, &BankedBus(FlatBus::new(), 2), &[0x0100, 0x4000, 0x7fff, 0xc000]);
+        assert_eq!(pcs, [0x4000, 0x7fff]);
+        assert!(traced_pcs(&mut tracer, &sink, &BankedBus(FlatBus::new(), 3), &[0x4000]).is_empty());
+
+        tracer.bank = Some(0);
+        let pcs = traced_pcs(&mut tracer, &sink, &BankedBus(FlatBus::new(), 2), &[0x0100, 0x4000]);
+        assert_eq!(pcs, [0x0100]);
+    }
+
+    #[test]
+    fn start_and_stop_frame_filter() {
+        let (mut tracer, sink) = tracer();
+        tracer.start_frame = 1;
+        tracer.stop_frame = Some(3);
+        let mem = FlatBus::new();
+        let mut frames = vec![];
+        for _ in 0..4 {
+            frames.push(traced_pcs(&mut tracer, &sink, &mem, &[0x0100]).len());
+            tracer.end_frame();
+        }
+        assert_eq!(frames, [0, 1, 1, 0]);
+    }
+}